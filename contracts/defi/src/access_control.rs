@@ -0,0 +1,80 @@
+use crate::*;
+
+#[derive(
+    BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug,
+)]
+pub enum Role {
+    /// `owner_id` of the contract, holds every other role
+    Owner,
+    /// can change the swap fee
+    FeeManager,
+    /// can pause and resume the contract
+    Pauser,
+}
+
+pub trait AccessControl {
+    /// grant `role` to `account_id`, only the owner can call it
+    fn grant_role(&mut self, account_id: AccountId, role: Role);
+
+    /// revoke `role` from `account_id`, only the owner can call it
+    fn revoke_role(&mut self, account_id: AccountId, role: Role);
+
+    fn has_role(&self, account_id: AccountId, role: Role) -> bool;
+
+    fn get_roles(&self, account_id: AccountId) -> Vec<Role>;
+}
+
+#[near_bindgen]
+impl AccessControl for DeFi {
+    fn grant_role(&mut self, account_id: AccountId, role: Role) {
+        self.assert_role(Role::Owner);
+        ensure(role != Role::Owner, DeFiError::OwnerRoleNotGrantable);
+        let mut roles = self.roles.get(&account_id).unwrap_or_default();
+        if !roles.contains(&role) {
+            roles.push(role);
+            self.roles.insert(&account_id, &roles);
+        }
+    }
+
+    fn revoke_role(&mut self, account_id: AccountId, role: Role) {
+        self.assert_role(Role::Owner);
+        if let Some(mut roles) = self.roles.get(&account_id) {
+            roles.retain(|r| r != &role);
+            if roles.is_empty() {
+                self.roles.remove(&account_id);
+            } else {
+                self.roles.insert(&account_id, &roles);
+            }
+        }
+    }
+
+    fn has_role(&self, account_id: AccountId, role: Role) -> bool {
+        self.internal_has_role(&account_id, role)
+    }
+
+    fn get_roles(&self, account_id: AccountId) -> Vec<Role> {
+        let mut roles = self.roles.get(&account_id).unwrap_or_default();
+        if account_id == self.owner_id {
+            roles.insert(0, Role::Owner);
+        }
+        roles
+    }
+}
+
+impl DeFi {
+    pub(crate) fn internal_has_role(&self, account_id: &AccountId, role: Role) -> bool {
+        account_id == &self.owner_id
+            || self
+                .roles
+                .get(account_id)
+                .is_some_and(|roles| roles.contains(&role))
+    }
+
+    /// panic if the predecessor doesn't hold `role`
+    pub(crate) fn assert_role(&self, role: Role) {
+        ensure(
+            self.internal_has_role(&env::predecessor_account_id(), role),
+            DeFiError::MissingRole(role),
+        );
+    }
+}
@@ -0,0 +1,248 @@
+use std::collections::{HashMap, HashSet};
+
+use near_contract_standards::fungible_token::core::ext_ft_core;
+use near_sdk::{assert_one_yocto, PromiseResult, StorageUsage};
+
+use crate::events::{LostFound, LostFoundClaimed, Refund, Withdraw};
+use crate::*;
+
+/// internal balances of a user, deposited via `ft_transfer_call`
+#[derive(BorshSerialize, BorshDeserialize, Default)]
+pub struct Account {
+    /// NEAR deposited via `storage_deposit`
+    pub near_amount: Balance,
+    /// bytes of the contract storage used by the account
+    pub storage_used: StorageUsage,
+    /// (token contract, balance) map
+    pub tokens: HashMap<AccountId, Balance>,
+    /// pools where the account holds LP shares or positions
+    pub pools: HashSet<u64>,
+}
+
+impl Account {
+    pub fn new(near_amount: Balance, storage_used: StorageUsage) -> Self {
+        Self {
+            near_amount,
+            storage_used,
+            tokens: HashMap::new(),
+            pools: HashSet::new(),
+        }
+    }
+
+    /// NEAR required for `storage_used`
+    pub fn storage_needed(&self) -> Balance {
+        Balance::from(self.storage_used) * env::storage_byte_cost()
+    }
+
+    /// NEAR of the storage deposit which isn't used
+    pub fn storage_available(&self) -> Balance {
+        self.near_amount.saturating_sub(self.storage_needed())
+    }
+
+    /// bytes the balance of `token_id` takes in the account
+    pub fn balance_storage_usage(token_id: &AccountId) -> StorageUsage {
+        (token_id.as_str().len() + 4 + 16) as StorageUsage
+    }
+
+    pub fn get_balance(&self, token_id: &AccountId) -> Balance {
+        self.tokens.get(token_id).copied().unwrap_or(0)
+    }
+
+    pub fn deposit(&mut self, token_id: &AccountId, amount: Balance) {
+        let balance = self
+            .get_balance(token_id)
+            .checked_add(amount)
+            .unwrap_or_else(|| DeFiError::AmountTooLarge.panic());
+        self.tokens.insert(token_id.clone(), balance);
+    }
+
+    pub fn withdraw(&mut self, token_id: &AccountId, amount: Balance) {
+        let balance = self.get_balance(token_id);
+        ensure(balance >= amount, DeFiError::NotEnoughDeposit);
+        if balance == amount {
+            self.tokens.remove(token_id);
+        } else {
+            self.tokens.insert(token_id.clone(), balance - amount);
+        }
+    }
+}
+
+#[near_bindgen]
+impl DeFi {
+    /// send `amount` of `token_id` from the internal balance back to the caller
+    #[payable]
+    pub fn withdraw(&mut self, token_id: AccountId, amount: U128) -> Promise {
+        assert_one_yocto();
+        ensure(!self.paused.withdraw, DeFiError::WithdrawPaused);
+        ensure(amount.0 > 0, DeFiError::ZeroAmount);
+        self.assert_enough_gas("withdraw");
+        let account_id = env::predecessor_account_id();
+        self.assert_no_flash_swap(&account_id);
+        let prev_storage = env::storage_usage();
+        self.internal_withdraw(&account_id, &token_id, amount.0);
+        self.internal_update_storage(&account_id, prev_storage);
+        ext_ft_core::ext(token_id.clone())
+            .with_attached_deposit(1)
+            .with_static_gas(self.gas_config.ft_transfer)
+            .ft_transfer(account_id.clone(), amount, None)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(self.gas_config.callback)
+                    .withdraw_callback(account_id, token_id, amount),
+            )
+    }
+
+    /// put the tokens back to the internal balance if the transfer failed
+    #[private]
+    pub fn withdraw_callback(&mut self, account_id: AccountId, token_id: AccountId, amount: U128) {
+        match env::promise_result(0) {
+            PromiseResult::Successful(_) => Withdraw {
+                account_id: &account_id,
+                token_id: &token_id,
+                amount: &amount,
+            }
+            .emit(),
+            _ => self.internal_refund(&account_id, &token_id, amount, "withdraw failed"),
+        }
+    }
+
+    /// move the lost and found balance of `token_id` to the internal balance of the owner
+    pub fn claim_lost_found(&mut self, token_id: AccountId) -> U128 {
+        self.assert_role(Role::Owner);
+        let account_id = env::predecessor_account_id();
+        let amount = self.lost_found.remove(&token_id).unwrap_or(0);
+        let prev_storage = env::storage_usage();
+        self.internal_deposit(&account_id, &token_id, amount);
+        self.internal_update_storage(&account_id, prev_storage);
+        LostFoundClaimed {
+            account_id: &account_id,
+            token_id: &token_id,
+            amount: &U128(amount),
+        }
+        .emit();
+        U128(amount)
+    }
+
+    /// refunds of `token_id` to accounts which unregistered before they arrived
+    pub fn get_lost_found(&self, token_id: AccountId) -> U128 {
+        U128(self.lost_found.get(&token_id).unwrap_or(0))
+    }
+
+    /// internal balances of `account_id`
+    pub fn get_deposits(&self, account_id: AccountId) -> HashMap<AccountId, U128> {
+        self.accounts
+            .get(&account_id)
+            .map(|account| {
+                account
+                    .tokens
+                    .into_iter()
+                    .map(|(token_id, balance)| (token_id, U128(balance)))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn get_deposit(&self, account_id: AccountId, token_id: AccountId) -> U128 {
+        U128(
+            self.accounts
+                .get(&account_id)
+                .map_or(0, |account| account.get_balance(&token_id)),
+        )
+    }
+}
+
+impl DeFi {
+    pub(crate) fn internal_deposit(
+        &mut self,
+        account_id: &AccountId,
+        token_id: &AccountId,
+        amount: Balance,
+    ) {
+        let mut account = self.internal_unwrap_account(account_id);
+        account.deposit(token_id, amount);
+        self.accounts.insert(account_id, &account);
+    }
+
+    /// credit `amount` of `token_id` to `account_id` from a callback. if the account
+    /// unregistered meanwhile keep it in the lost and found balance. return whether it
+    /// was credited
+    pub(crate) fn internal_deposit_or_lost_found(
+        &mut self,
+        account_id: &AccountId,
+        token_id: &AccountId,
+        amount: Balance,
+        reason: &str,
+    ) -> bool {
+        if self.accounts.contains_key(account_id) {
+            let prev_storage = env::storage_usage();
+            self.internal_deposit(account_id, token_id, amount);
+            self.internal_track_storage(account_id, prev_storage);
+            return true;
+        }
+        let balance = self.lost_found.get(token_id).unwrap_or(0);
+        self.lost_found.insert(token_id, &(balance + amount));
+        LostFound {
+            account_id,
+            token_id,
+            amount: &U128(amount),
+            reason,
+        }
+        .emit();
+        false
+    }
+
+    /// put `amount` of `token_id` back to the internal balance of `account_id`
+    /// after a failed transfer
+    pub(crate) fn internal_refund(
+        &mut self,
+        account_id: &AccountId,
+        token_id: &AccountId,
+        amount: U128,
+        reason: &str,
+    ) {
+        if self.internal_deposit_or_lost_found(account_id, token_id, amount.0, reason) {
+            Refund {
+                account_id,
+                token_id,
+                amount: &amount,
+                reason,
+            }
+            .emit();
+        }
+    }
+
+    pub(crate) fn internal_withdraw(
+        &mut self,
+        account_id: &AccountId,
+        token_id: &AccountId,
+        amount: Balance,
+    ) {
+        let mut account = self.internal_unwrap_account(account_id);
+        account.withdraw(token_id, amount);
+        self.accounts.insert(account_id, &account);
+    }
+
+    /// record whether `account_id` still holds LP shares or positions of the pool `pool_id`
+    pub(crate) fn internal_set_joined_pool(
+        &mut self,
+        account_id: &AccountId,
+        pool_id: u64,
+        joined: bool,
+    ) {
+        let mut account = self.internal_unwrap_account(account_id);
+        let changed = if joined {
+            account.pools.insert(pool_id)
+        } else {
+            account.pools.remove(&pool_id)
+        };
+        if changed {
+            self.accounts.insert(account_id, &account);
+        }
+    }
+
+    pub(crate) fn internal_unwrap_account(&self, account_id: &AccountId) -> Account {
+        self.accounts
+            .get(account_id)
+            .unwrap_or_else(|| DeFiError::AccountNotRegistered.panic())
+    }
+}
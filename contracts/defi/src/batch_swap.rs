@@ -0,0 +1,249 @@
+use crate::errors::{check, ensure};
+use crate::*;
+
+/// one swap of `batch_swap`, like the arguments of `swap`
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct SwapRequest {
+    pub pool_id: u64,
+    pub token_in: AccountId,
+    pub amount_in: U128,
+    pub token_out: AccountId,
+}
+
+#[near_bindgen]
+impl DeFi {
+    /// run `swaps` in order against the caller's internal balances, the output of a swap can
+    /// be the input of a later one. a swap is rejected like by `swap`, or when `swap` would
+    /// panic on it, e.g. for an unknown pool or token, a short balance at its turn or a
+    /// storage deposit which can't pay for a new output token. with
+    /// `all_or_nothing` the first rejected swap panics and none of the swaps happen.
+    /// return the result of every swap
+    pub fn batch_swap(
+        &mut self,
+        swaps: Vec<SwapRequest>,
+        deadline: Option<U64>,
+        referral_id: Option<AccountId>,
+        all_or_nothing: Option<bool>,
+    ) -> Vec<SwapResult> {
+        let account_id = env::predecessor_account_id();
+        self.assert_no_flash_swap(&account_id);
+        self.assert_referral(&account_id, referral_id.as_ref());
+        let options = SwapOptions {
+            deadline,
+            unwrap_near: false,
+            referral_id,
+        };
+        let all_or_nothing = all_or_nothing.unwrap_or(false);
+        let mut results = Vec::with_capacity(swaps.len());
+        for (index, request) in swaps.into_iter().enumerate() {
+            let result = match self.check_swap_request(&account_id, &request) {
+                Err(error) => {
+                    log!("swap {} rejected: {}", index, error);
+                    let fee = self
+                        .pools
+                        .get(request.pool_id)
+                        .map_or(0, |pool| pool.fee_of(request.amount_in.0));
+                    self.internal_new_swap_result(
+                        request.token_in,
+                        request.amount_in,
+                        request.token_out,
+                        fee,
+                    )
+                }
+                Ok(()) => self.internal_swap(
+                    &account_id,
+                    request.pool_id,
+                    request.token_in,
+                    request.amount_in,
+                    request.token_out,
+                    &options,
+                ),
+            };
+            ensure(
+                !all_or_nothing || result.status == SwapStatus::Success,
+                DeFiError::BatchSwapRejected(index),
+            );
+            results.push(result);
+        }
+        results
+    }
+}
+
+impl DeFi {
+    /// the error `internal_swap` would panic with for `request` of `account_id`
+    fn check_swap_request(
+        &self,
+        account_id: &AccountId,
+        request: &SwapRequest,
+    ) -> Result<(), DeFiError> {
+        let pool = self
+            .pools
+            .get(request.pool_id)
+            .ok_or(DeFiError::PoolNotFound)?;
+        pool.try_swap_return(&request.token_in, request.amount_in.0, &request.token_out)?;
+        let account = self.internal_unwrap_account(account_id);
+        let balance = account.get_balance(&request.token_in);
+        check(balance >= request.amount_in.0, DeFiError::NotEnoughDeposit)?;
+        if account.tokens.contains_key(&request.token_out) {
+            return Ok(());
+        }
+        // the storage deposit pays for a new balance of `token_out`, less the balance of
+        // `token_in` if it is spent whole
+        let mut storage = Account::balance_storage_usage(&request.token_out);
+        if balance == request.amount_in.0 {
+            storage = storage.saturating_sub(Account::balance_storage_usage(&request.token_in));
+        }
+        check(
+            Balance::from(storage) * env::storage_byte_cost() <= account.storage_available(),
+            DeFiError::NotEnoughStorageDeposit,
+        )
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::test_utils::{deposit, pool_contract, STORAGE_DEPOSIT};
+
+    /// pool 0 with 1000 TokenA and 1000 TokenB, `accounts(1)` holds 100 TokenA
+    fn contract() -> (DeFi, VMContextBuilder) {
+        let (mut contract, mut context) = pool_contract(1000);
+        deposit(&mut contract, &mut context, accounts(1), accounts(3), 100);
+        (contract, context)
+    }
+
+    fn request(token_in: AccountId, amount_in: Balance, token_out: AccountId) -> SwapRequest {
+        SwapRequest {
+            pool_id: 0,
+            token_in,
+            amount_in: U128(amount_in),
+            token_out,
+        }
+    }
+
+    #[test]
+    fn swaps_in_order() {
+        let (mut contract, _) = contract();
+        let results = contract.batch_swap(
+            vec![
+                request(accounts(3), 50, accounts(4)),
+                request(accounts(4), 40, accounts(3)),
+            ],
+            None,
+            None,
+            None,
+        );
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|r| r.status == SwapStatus::Success));
+        assert_eq!(results[1].swap_id.0, results[0].swap_id.0 + 1);
+        let amount_b = results[0].amount_out.0 - 40;
+        assert_eq!(contract.get_deposit(accounts(1), accounts(4)).0, amount_b);
+        assert_eq!(
+            contract.get_deposit(accounts(1), accounts(3)).0,
+            50 + results[1].amount_out.0
+        );
+    }
+
+    #[test]
+    fn short_balance_rejected() {
+        let (mut contract, _) = contract();
+        let results = contract.batch_swap(
+            vec![
+                request(accounts(3), 200, accounts(4)),
+                request(accounts(3), 50, accounts(4)),
+            ],
+            None,
+            None,
+            None,
+        );
+        assert_eq!(results[0].status, SwapStatus::Rejected);
+        assert_eq!(results[1].status, SwapStatus::Success);
+        assert_eq!(contract.get_deposit(accounts(1), accounts(3)).0, 50);
+    }
+
+    #[test]
+    fn invalid_swaps_rejected() {
+        let (mut contract, _) = contract();
+        let results = contract.batch_swap(
+            vec![
+                SwapRequest {
+                    pool_id: 7,
+                    ..request(accounts(3), 10, accounts(4))
+                },
+                request(accounts(5), 10, accounts(4)),
+                request(accounts(3), 0, accounts(4)),
+                request(accounts(3), 10, accounts(4)),
+            ],
+            None,
+            None,
+            None,
+        );
+        let statuses: Vec<_> = results.iter().map(|r| r.status).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                SwapStatus::Rejected,
+                SwapStatus::Rejected,
+                SwapStatus::Rejected,
+                SwapStatus::Success
+            ]
+        );
+        assert_eq!(results[0].fee.0, 0);
+        assert_eq!(contract.get_deposit(accounts(1), accounts(3)).0, 90);
+    }
+
+    #[test]
+    fn short_storage_rejected() {
+        let (mut contract, mut context) = contract();
+        // pool 1 of TokenA and TokenC, `accounts(1)` holds TokenA and TokenB without spare storage
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(STORAGE_DEPOSIT)
+            .build());
+        contract.add_whitelisted_tokens(vec![accounts(5)]);
+        contract.add_simple_pool(vec![accounts(3), accounts(5)], 0);
+        testing_env!(context.attached_deposit(0).build());
+        deposit(&mut contract, &mut context, accounts(0), accounts(3), 1000);
+        deposit(&mut contract, &mut context, accounts(0), accounts(5), 1000);
+        contract.add_liquidity(1, vec![U128(1000), U128(1000)], None);
+        deposit(&mut contract, &mut context, accounts(1), accounts(4), 10);
+        testing_env!(context.attached_deposit(1).build());
+        contract.storage_withdraw(None);
+
+        let results = contract.batch_swap(
+            vec![
+                SwapRequest {
+                    pool_id: 1,
+                    ..request(accounts(3), 10, accounts(5))
+                },
+                request(accounts(3), 10, accounts(4)),
+            ],
+            None,
+            None,
+            None,
+        );
+        assert_eq!(results[0].status, SwapStatus::Rejected);
+        assert_eq!(results[1].status, SwapStatus::Success);
+        assert_eq!(contract.get_deposit(accounts(1), accounts(3)).0, 90);
+        assert_eq!(contract.get_deposit(accounts(1), accounts(5)).0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "E062: swap 1 of the batch was rejected")]
+    fn all_or_nothing() {
+        let (mut contract, _) = contract();
+        contract.batch_swap(
+            vec![
+                request(accounts(3), 50, accounts(4)),
+                request(accounts(3), 200, accounts(4)),
+            ],
+            None,
+            None,
+            Some(true),
+        );
+    }
+}
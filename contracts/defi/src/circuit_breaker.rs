@@ -0,0 +1,218 @@
+use near_sdk::BlockHeight;
+
+use crate::events::CircuitBreakerChanged;
+use crate::utils::price_move;
+use crate::*;
+
+/// limits of the price moves caused by swaps, in basis points of the price of the token
+/// swapped in. zero disables a limit
+#[derive(
+    BorshDeserialize,
+    BorshSerialize,
+    Deserialize,
+    Serialize,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Debug,
+)]
+pub struct CircuitBreaker {
+    /// max move of the price of a pool by a single swap
+    pub max_price_impact: u32,
+    /// max sum of the price moves of a pool within a window of `window_blocks`
+    pub max_window_move: u32,
+    /// length of the windows in blocks, windows start at multiples of it
+    pub window_blocks: BlockHeight,
+}
+
+/// price moves of a pool in the window `index`
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct PriceWindow {
+    pub index: u64,
+    pub moved: u32,
+}
+
+pub trait CircuitBreakerManagement {
+    /// replace the limits, only the owner can call it
+    fn set_circuit_breaker(&mut self, circuit_breaker: CircuitBreaker);
+
+    fn get_circuit_breaker(&self) -> CircuitBreaker;
+
+    /// sum of the price moves of the pool `pool_id` in the current window, in basis points
+    fn get_window_price_move(&self, pool_id: u64) -> u32;
+}
+
+#[near_bindgen]
+impl CircuitBreakerManagement for DeFi {
+    fn set_circuit_breaker(&mut self, circuit_breaker: CircuitBreaker) {
+        self.assert_role(Role::Owner);
+        ensure(
+            circuit_breaker.max_window_move == 0 || circuit_breaker.window_blocks > 0,
+            DeFiError::InvalidCircuitBreaker,
+        );
+        self.circuit_breaker = circuit_breaker;
+        CircuitBreakerChanged {
+            account_id: &env::predecessor_account_id(),
+            circuit_breaker: &self.circuit_breaker,
+        }
+        .emit();
+    }
+
+    fn get_circuit_breaker(&self) -> CircuitBreaker {
+        self.circuit_breaker
+    }
+
+    fn get_window_price_move(&self, pool_id: u64) -> u32 {
+        self.internal_window_price_move(pool_id)
+    }
+}
+
+impl DeFi {
+    fn current_window(&self) -> u64 {
+        env::block_height() / self.circuit_breaker.window_blocks.max(1)
+    }
+
+    fn internal_window_price_move(&self, pool_id: u64) -> u32 {
+        self.price_windows
+            .get(&pool_id)
+            .filter(|window| window.index == self.current_window())
+            .map_or(0, |window| window.moved)
+    }
+
+    /// price move of swapping `amount_in` of `token_in` in `pool`, or why the circuit
+    /// breaker rejects the swap. nothing is computed while both limits are disabled
+    pub(crate) fn check_price_move(
+        &self,
+        pool_id: u64,
+        pool: &Pool,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+    ) -> Result<u32, &'static str> {
+        if self.circuit_breaker_disabled() {
+            return Ok(0);
+        }
+        self.check_move(pool_id, pool.price_impact(token_in, amount_in, token_out))
+    }
+
+    /// price move of lending `amount` out of `reserve` of the pool `pool_id` by a flash swap,
+    /// or why the circuit breaker rejects the loan. the price of the lent token goes up like
+    /// in a constant product pool, the other curves move it less
+    pub(crate) fn check_loan_move(
+        &self,
+        pool_id: u64,
+        reserve: Balance,
+        amount: Balance,
+    ) -> Result<u32, &'static str> {
+        if self.circuit_breaker_disabled() {
+            return Ok(0);
+        }
+        self.check_move(
+            pool_id,
+            price_move(U256::from(reserve - amount), U256::from(reserve)),
+        )
+    }
+
+    fn circuit_breaker_disabled(&self) -> bool {
+        self.circuit_breaker.max_price_impact == 0 && self.circuit_breaker.max_window_move == 0
+    }
+
+    fn check_move(&self, pool_id: u64, moved: u32) -> Result<u32, &'static str> {
+        let limits = self.circuit_breaker;
+        if limits.max_price_impact > 0 && moved > limits.max_price_impact {
+            return Err("price impact is too high");
+        }
+        if limits.max_window_move > 0
+            && self
+                .internal_window_price_move(pool_id)
+                .saturating_add(moved)
+                > limits.max_window_move
+        {
+            return Err("price moved too much in this window");
+        }
+        Ok(moved)
+    }
+
+    /// add the price move of a swap to the current window of the pool `pool_id`
+    pub(crate) fn record_price_move(&mut self, pool_id: u64, moved: u32) {
+        if self.circuit_breaker.max_window_move == 0 {
+            return;
+        }
+        let window = PriceWindow {
+            index: self.current_window(),
+            moved: self
+                .internal_window_price_move(pool_id)
+                .saturating_add(moved),
+        };
+        self.price_windows.insert(&pool_id, &window);
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::test_utils::{deposit, pool_contract};
+
+    /// pool 0 with 1000 TokenA and 1000 TokenB, `accounts(1)` holds 100 TokenA
+    fn contract() -> (DeFi, VMContextBuilder) {
+        let (mut contract, mut context) = pool_contract(1000);
+        deposit(&mut contract, &mut context, accounts(1), accounts(3), 100);
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        (contract, context)
+    }
+
+    #[test]
+    fn price_impact_limit() {
+        let (mut contract, mut context) = contract();
+        contract.set_circuit_breaker(CircuitBreaker {
+            max_price_impact: 1000,
+            ..Default::default()
+        });
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        let result = contract.swap(0, accounts(3), U128(100), accounts(4), None);
+        assert_eq!(result.status, SwapStatus::Rejected);
+        assert_eq!(contract.get_deposit(accounts(1), accounts(3)).0, 100);
+
+        let result = contract.swap(0, accounts(3), U128(30), accounts(4), None);
+        assert_eq!(result.status, SwapStatus::Success);
+    }
+
+    #[test]
+    fn window_limit() {
+        let (mut contract, mut context) = contract();
+        contract.set_circuit_breaker(CircuitBreaker {
+            max_window_move: 1000,
+            window_blocks: 10,
+            ..Default::default()
+        });
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        let result = contract.swap(0, accounts(3), U128(30), accounts(4), None);
+        assert_eq!(result.status, SwapStatus::Success);
+        let moved = contract.get_window_price_move(0);
+        assert!(moved > 500 && moved < 1000);
+
+        let result = contract.swap(0, accounts(3), U128(30), accounts(4), None);
+        assert_eq!(result.status, SwapStatus::Rejected);
+        assert_eq!(contract.get_window_price_move(0), moved);
+
+        testing_env!(context.block_index(10).build());
+        assert_eq!(contract.get_window_price_move(0), 0);
+        let result = contract.swap(0, accounts(3), U128(30), accounts(4), None);
+        assert_eq!(result.status, SwapStatus::Success);
+    }
+
+    #[test]
+    #[should_panic(expected = "E061: window move limit needs window blocks")]
+    fn window_without_blocks() {
+        let (mut contract, _) = contract();
+        contract.set_circuit_breaker(CircuitBreaker {
+            max_window_move: 1000,
+            ..Default::default()
+        });
+    }
+}
@@ -0,0 +1,957 @@
+use near_sdk::collections::{LookupMap, TreeMap};
+use near_sdk::{AccountId, Balance};
+
+use crate::errors::{check, ensure};
+use crate::events::{AddPosition, ClaimPositionFees, RemovePosition};
+use crate::pool::{assert_token_index, token_index};
+use crate::simple_pool::add_reserve;
+use crate::utils::{price_move, U256};
+use crate::*;
+
+/// the price of a tick is `1.0001^tick`, the range keeps prices within 2^-64 .. 2^64
+pub const MIN_TICK: i32 = -443_636;
+pub const MAX_TICK: i32 = 443_636;
+/// `2^128 / sqrt(1.0001)^(2^i)`, multiplied for the set bits of a tick
+const TICK_RATIOS: [u128; 19] = [
+    0xfffcb933bd6fad37aa2d162d1a594001,
+    0xfff97272373d413259a46990580e2139,
+    0xfff2e50f5f656932ef12357cf3c7fdcb,
+    0xffe5caca7e10e4e61c3624eaa0941ccf,
+    0xffcb9843d60f6159c9db58835c926643,
+    0xff973b41fa98c081472e6896dfb254bf,
+    0xff2ea16466c96a3843ec78b326b52860,
+    0xfe5dee046a99a2a811c461f1969c3052,
+    0xfcbe86c7900a88aedcffc83b479aa3a3,
+    0xf987a7253ac413176f2b074cf7815e53,
+    0xf3392b0822b70005940c7a398e4b70f2,
+    0xe7159475a2c29b7443b29c7fa6e889d8,
+    0xd097f3bdfd2022b8845ad8f792aa5825,
+    0xa9f746462d870fdf8a65dc1f90e061e4,
+    0x70d869a156d2a1b890bb3df62baf32f6,
+    0x31be135f97d08fd981231505542fcfa5,
+    0x9aa508b5b7a84e1c677de54f3e99bc8,
+    0x5d6af8dedb81196699c329225ee604,
+    0x2216e584f5fa1ea926041bedfe97,
+];
+
+/// square root of the price `1.0001^tick` of token 0 in token 1, Q64.64 rounded up
+pub fn sqrt_price_at_tick(tick: i32) -> u128 {
+    ensure(
+        (MIN_TICK..=MAX_TICK).contains(&tick),
+        DeFiError::InvalidTickRange,
+    );
+    let abs_tick = tick.unsigned_abs();
+    let mut ratio = U256::one() << 128;
+    for (i, tick_ratio) in TICK_RATIOS.iter().enumerate() {
+        if abs_tick & (1 << i) != 0 {
+            ratio = (ratio * U256::from(*tick_ratio)) >> 128;
+        }
+    }
+    if tick > 0 {
+        ratio = U256::MAX / ratio;
+    }
+    div_round_up(ratio, U256::one() << 64).as_u128()
+}
+
+/// greatest tick whose square root price is at most `sqrt_price`
+pub fn tick_at_sqrt_price(sqrt_price: u128) -> i32 {
+    let (mut low, mut high) = (MIN_TICK, MAX_TICK);
+    while low < high {
+        let mid = low + (high - low + 1) / 2;
+        if sqrt_price_at_tick(mid) <= sqrt_price {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+    low
+}
+
+fn div_round_up(a: U256, b: U256) -> U256 {
+    let (quotient, remainder) = a.div_mod(b);
+    if remainder.is_zero() {
+        quotient
+    } else {
+        quotient + U256::one()
+    }
+}
+
+fn to_balance(amount: U256) -> Balance {
+    ensure(amount <= U256::from(u128::MAX), DeFiError::AmountTooLarge);
+    amount.as_u128()
+}
+
+/// token 0 of `liquidity` between the square root prices `lower` <= `upper`,
+/// `liquidity / lower - liquidity / upper`
+fn amount0_delta(lower: u128, upper: u128, liquidity: Balance, round_up: bool) -> U256 {
+    if lower == upper {
+        return U256::zero();
+    }
+    let numerator = U256::from(liquidity) << 64;
+    if round_up {
+        div_round_up(numerator, U256::from(lower)) - numerator / U256::from(upper)
+    } else {
+        (numerator / U256::from(lower)).saturating_sub(div_round_up(numerator, U256::from(upper)))
+    }
+}
+
+/// token 1 of `liquidity` between the square root prices `lower` <= `upper`,
+/// `liquidity * (upper - lower)`
+fn amount1_delta(lower: u128, upper: u128, liquidity: Balance, round_up: bool) -> U256 {
+    let product = U256::from(liquidity) * U256::from(upper - lower);
+    if round_up {
+        div_round_up(product, U256::one() << 64)
+    } else {
+        product >> 64
+    }
+}
+
+fn liquidity_for_amount0(lower: u128, upper: u128, amount: Balance) -> U256 {
+    let intermediate = (U256::from(lower) * U256::from(upper)) >> 64;
+    U256::from(amount)
+        .checked_mul(intermediate)
+        .unwrap_or_else(|| DeFiError::AmountTooLarge.panic())
+        / U256::from(upper - lower)
+}
+
+fn liquidity_for_amount1(lower: u128, upper: u128, amount: Balance) -> U256 {
+    (U256::from(amount) << 64) / U256::from(upper - lower)
+}
+
+fn add_liquidity_delta(liquidity: Balance, delta: i128) -> Balance {
+    if delta >= 0 {
+        liquidity.checked_add(delta.unsigned_abs())
+    } else {
+        liquidity.checked_sub(delta.unsigned_abs())
+    }
+    .unwrap_or_else(|| DeFiError::NotEnoughLiquidity.panic())
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Default)]
+pub struct Tick {
+    /// liquidity of the positions bounded by the tick
+    liquidity_gross: Balance,
+    /// liquidity added to the active liquidity when the price crosses the tick upwards
+    liquidity_net: i128,
+    /// fee growth of each token on the other side of the tick from the current price
+    fee_growth_outside: [u128; 2],
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct Position {
+    pub owner_id: AccountId,
+    pub lower_tick: i32,
+    pub upper_tick: i32,
+    pub liquidity: Balance,
+    /// fee growth inside the range when the fees were last claimed
+    fee_growth_inside_last: [u128; 2],
+}
+
+/// position as returned by the views
+#[derive(Serialize)]
+pub struct PositionInfo {
+    pub position_id: U64,
+    pub owner_id: AccountId,
+    pub lower_tick: i32,
+    pub upper_tick: i32,
+    pub liquidity: U128,
+    /// fees of each token earned since the last claim
+    pub unclaimed_fees: Vec<U128>,
+}
+
+/// state of a swap computed without touching the pool
+struct SwapComputation {
+    amount_out: Balance,
+    sqrt_price: u128,
+    current_tick: i32,
+    liquidity: Balance,
+    /// fee growth of `token_in` after the swap
+    fee_growth_global: u128,
+    /// crossed ticks with the fee growth of `token_in` when they were crossed
+    crossed_ticks: Vec<(i32, u128)>,
+}
+
+/// concentrated liquidity pool of two tokens, every position provides x * y = k liquidity
+/// only while the price is within its ticks. fees are shared by the positions in range
+/// and tracked per position with the fee growth per liquidity, Q64.64 wrapping
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct ConcentratedPool {
+    /// token contracts of the pool, the price is of token 0 in token 1
+    pub token_account_ids: Vec<AccountId>,
+    /// reserves of each token, unclaimed fees included
+    pub amounts: Vec<Balance>,
+    /// swap fee in basis points
+    pub total_fee: u32,
+    /// positions only use ticks which are multiples of it
+    pub tick_spacing: u32,
+    /// square root of the current price, Q64.64
+    pub sqrt_price: u128,
+    /// greatest tick at or below the current price
+    pub current_tick: i32,
+    /// liquidity of the positions in range
+    pub liquidity: Balance,
+    /// liquidity of every position
+    pub total_liquidity: Balance,
+    fee_growth_global: [u128; 2],
+    /// initialized ticks, bounds of at least one position
+    ticks: TreeMap<i32, Tick>,
+    positions: LookupMap<u64, Position>,
+    /// (account, position ids) map
+    account_positions: LookupMap<AccountId, Vec<u64>>,
+    next_position_id: u64,
+}
+
+impl ConcentratedPool {
+    pub fn new(
+        pool_id: u64,
+        token_account_ids: Vec<AccountId>,
+        total_fee: u32,
+        tick_spacing: u32,
+        initial_tick: i32,
+    ) -> Self {
+        ensure(total_fee < FEE_DIVISOR, DeFiError::FeeTooLarge);
+        ensure(tick_spacing > 0, DeFiError::InvalidTickRange);
+        Self {
+            amounts: vec![0; token_account_ids.len()],
+            token_account_ids,
+            total_fee,
+            tick_spacing,
+            sqrt_price: sqrt_price_at_tick(initial_tick),
+            current_tick: initial_tick,
+            liquidity: 0,
+            total_liquidity: 0,
+            fee_growth_global: [0; 2],
+            ticks: TreeMap::new(StoreKey::Ticks { pool_id }),
+            positions: LookupMap::new(StoreKey::Positions { pool_id }),
+            account_positions: LookupMap::new(StoreKey::AccountPositions { pool_id }),
+            next_position_id: 0,
+        }
+    }
+
+    /// liquidity of every position of `account_id`
+    pub fn share_balance_of(&self, account_id: &AccountId) -> Balance {
+        self.account_positions
+            .get(account_id)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|position_id| self.positions.get(position_id))
+            .map(|position| position.liquidity)
+            .sum()
+    }
+
+    fn is_in_range(&self, lower_tick: i32, upper_tick: i32) -> bool {
+        lower_tick <= self.current_tick && self.current_tick < upper_tick
+    }
+
+    fn fee_growth_inside(&self, lower_tick: i32, upper_tick: i32) -> [u128; 2] {
+        let lower = self.ticks.get(&lower_tick).unwrap_or_default();
+        let upper = self.ticks.get(&upper_tick).unwrap_or_default();
+        let mut inside = [0; 2];
+        for (i, global) in self.fee_growth_global.iter().enumerate() {
+            let below = if self.current_tick >= lower_tick {
+                lower.fee_growth_outside[i]
+            } else {
+                global.wrapping_sub(lower.fee_growth_outside[i])
+            };
+            let above = if self.current_tick < upper_tick {
+                upper.fee_growth_outside[i]
+            } else {
+                global.wrapping_sub(upper.fee_growth_outside[i])
+            };
+            inside[i] = global.wrapping_sub(below).wrapping_sub(above);
+        }
+        inside
+    }
+
+    fn fees_of(position: &Position, fee_growth_inside: &[u128; 2]) -> [Balance; 2] {
+        let mut fees = [0; 2];
+        for (i, fee) in fees.iter_mut().enumerate() {
+            let growth = fee_growth_inside[i].wrapping_sub(position.fee_growth_inside_last[i]);
+            *fee = ((U256::from(position.liquidity) * U256::from(growth)) >> 64).as_u128();
+        }
+        fees
+    }
+
+    /// add or remove `liquidity` bounded by `tick`, the tick is dropped once unused
+    fn update_tick(&mut self, tick: i32, liquidity: Balance, add: bool, is_upper: bool) {
+        let mut info = self.ticks.get(&tick).unwrap_or_default();
+        if add {
+            if info.liquidity_gross == 0 && tick <= self.current_tick {
+                // all the fees so far were earned below the tick
+                info.fee_growth_outside = self.fee_growth_global;
+            }
+            info.liquidity_gross = add_reserve(info.liquidity_gross, liquidity);
+        } else {
+            info.liquidity_gross -= liquidity;
+        }
+        let delta = liquidity as i128;
+        info.liquidity_net += if add != is_upper { delta } else { -delta };
+        if info.liquidity_gross == 0 {
+            self.ticks.remove(&tick);
+        } else {
+            self.ticks.insert(&tick, &info);
+        }
+    }
+
+    fn amounts_for_liquidity(
+        &self,
+        lower_tick: i32,
+        upper_tick: i32,
+        liquidity: Balance,
+        round_up: bool,
+    ) -> [Balance; 2] {
+        let lower = sqrt_price_at_tick(lower_tick);
+        let upper = sqrt_price_at_tick(upper_tick);
+        let sqrt_price = self.sqrt_price.clamp(lower, upper);
+        [
+            to_balance(amount0_delta(sqrt_price, upper, liquidity, round_up)),
+            to_balance(amount1_delta(lower, sqrt_price, liquidity, round_up)),
+        ]
+    }
+
+    fn liquidity_for_amounts(&self, lower_tick: i32, upper_tick: i32, amounts: &[Balance]) -> U256 {
+        let lower = sqrt_price_at_tick(lower_tick);
+        let upper = sqrt_price_at_tick(upper_tick);
+        // the token 0 needed for the liquidity is rounded up by at most one
+        let amount0 = amounts[0].saturating_sub(1);
+        if self.sqrt_price <= lower {
+            liquidity_for_amount0(lower, upper, amount0)
+        } else if self.sqrt_price < upper {
+            liquidity_for_amount0(self.sqrt_price, upper, amount0).min(liquidity_for_amount1(
+                lower,
+                self.sqrt_price,
+                amounts[1],
+            ))
+        } else {
+            liquidity_for_amount1(lower, upper, amounts[1])
+        }
+    }
+
+    fn assert_position(&self, account_id: &AccountId, position_id: u64) -> Position {
+        let position = self
+            .positions
+            .get(&position_id)
+            .unwrap_or_else(|| DeFiError::PositionNotFound.panic());
+        ensure(
+            &position.owner_id == account_id,
+            DeFiError::NotPositionOwner,
+        );
+        position
+    }
+
+    /// open a position of `account_id` between the ticks with at most `amounts`, which is
+    /// updated to the amounts used. return the id and the liquidity of the position
+    pub fn add_position(
+        &mut self,
+        account_id: &AccountId,
+        lower_tick: i32,
+        upper_tick: i32,
+        amounts: &mut [Balance],
+    ) -> (u64, Balance) {
+        ensure(amounts.len() == 2, DeFiError::InvalidAmounts);
+        let spacing = self.tick_spacing as i32;
+        ensure(
+            lower_tick < upper_tick
+                && lower_tick >= MIN_TICK
+                && upper_tick <= MAX_TICK
+                && lower_tick % spacing == 0
+                && upper_tick % spacing == 0,
+            DeFiError::InvalidTickRange,
+        );
+        let liquidity = self.liquidity_for_amounts(lower_tick, upper_tick, amounts);
+        ensure(
+            liquidity <= U256::from(i128::MAX as u128),
+            DeFiError::AmountTooLarge,
+        );
+        let liquidity = liquidity.as_u128();
+        ensure(liquidity > 0, DeFiError::ZeroAmount);
+
+        self.update_tick(lower_tick, liquidity, true, false);
+        self.update_tick(upper_tick, liquidity, true, true);
+        if self.is_in_range(lower_tick, upper_tick) {
+            self.liquidity += liquidity;
+        }
+        self.total_liquidity = add_reserve(self.total_liquidity, liquidity);
+        let used = self.amounts_for_liquidity(lower_tick, upper_tick, liquidity, true);
+        for ((amount, reserve), used) in amounts.iter_mut().zip(self.amounts.iter_mut()).zip(used) {
+            *reserve = add_reserve(*reserve, used);
+            *amount = used;
+        }
+
+        let position_id = self.next_position_id;
+        self.next_position_id += 1;
+        self.positions.insert(
+            &position_id,
+            &Position {
+                owner_id: account_id.clone(),
+                lower_tick,
+                upper_tick,
+                liquidity,
+                fee_growth_inside_last: self.fee_growth_inside(lower_tick, upper_tick),
+            },
+        );
+        let mut position_ids = self.account_positions.get(account_id).unwrap_or_default();
+        position_ids.push(position_id);
+        self.account_positions.insert(account_id, &position_ids);
+        (position_id, liquidity)
+    }
+
+    /// take `liquidity` out of the position, the position is closed once it is empty.
+    /// return the token amounts of the liquidity and the fees earned
+    pub fn remove_position(
+        &mut self,
+        account_id: &AccountId,
+        position_id: u64,
+        liquidity: Balance,
+        min_amounts: &[Balance],
+    ) -> ([Balance; 2], [Balance; 2]) {
+        ensure(min_amounts.len() == 2, DeFiError::InvalidAmounts);
+        let mut position = self.assert_position(account_id, position_id);
+        ensure(
+            liquidity > 0 && liquidity <= position.liquidity,
+            DeFiError::NotEnoughLiquidity,
+        );
+        let fee_growth_inside = self.fee_growth_inside(position.lower_tick, position.upper_tick);
+        let fees = Self::fees_of(&position, &fee_growth_inside);
+        let amounts =
+            self.amounts_for_liquidity(position.lower_tick, position.upper_tick, liquidity, false);
+        for (amount, min_amount) in amounts.iter().zip(min_amounts.iter()) {
+            ensure(*amount >= *min_amount, DeFiError::MinAmountNotReached);
+        }
+
+        self.update_tick(position.lower_tick, liquidity, false, false);
+        self.update_tick(position.upper_tick, liquidity, false, true);
+        if self.is_in_range(position.lower_tick, position.upper_tick) {
+            self.liquidity -= liquidity;
+        }
+        self.total_liquidity -= liquidity;
+        for ((reserve, amount), fee) in self.amounts.iter_mut().zip(amounts).zip(fees) {
+            *reserve -= amount + fee;
+        }
+
+        position.liquidity -= liquidity;
+        if position.liquidity == 0 {
+            self.positions.remove(&position_id);
+            let mut position_ids = self.account_positions.get(account_id).unwrap_or_default();
+            position_ids.retain(|id| *id != position_id);
+            if position_ids.is_empty() {
+                self.account_positions.remove(account_id);
+            } else {
+                self.account_positions.insert(account_id, &position_ids);
+            }
+        } else {
+            position.fee_growth_inside_last = fee_growth_inside;
+            self.positions.insert(&position_id, &position);
+        }
+        (amounts, fees)
+    }
+
+    /// take the fees earned by the position since the last claim
+    pub fn claim_fees(&mut self, account_id: &AccountId, position_id: u64) -> [Balance; 2] {
+        let mut position = self.assert_position(account_id, position_id);
+        let fee_growth_inside = self.fee_growth_inside(position.lower_tick, position.upper_tick);
+        let fees = Self::fees_of(&position, &fee_growth_inside);
+        for (reserve, fee) in self.amounts.iter_mut().zip(fees) {
+            *reserve -= fee;
+        }
+        position.fee_growth_inside_last = fee_growth_inside;
+        self.positions.insert(&position_id, &position);
+        fees
+    }
+
+    pub fn position_info(&self, position_id: u64) -> Option<PositionInfo> {
+        self.positions.get(&position_id).map(|position| {
+            let fee_growth_inside =
+                self.fee_growth_inside(position.lower_tick, position.upper_tick);
+            PositionInfo {
+                position_id: U64(position_id),
+                unclaimed_fees: Self::fees_of(&position, &fee_growth_inside)
+                    .into_iter()
+                    .map(U128)
+                    .collect(),
+                owner_id: position.owner_id,
+                lower_tick: position.lower_tick,
+                upper_tick: position.upper_tick,
+                liquidity: U128(position.liquidity),
+            }
+        })
+    }
+
+    pub fn positions_of(&self, account_id: &AccountId) -> Vec<PositionInfo> {
+        self.account_positions
+            .get(account_id)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|position_id| self.position_info(position_id))
+            .collect()
+    }
+
+    /// walk the price tick by tick until `amount_in` is used, the fee of every step
+    /// goes to the liquidity in range at that step
+    fn compute_swap(&self, zero_for_one: bool, amount_in: Balance) -> SwapComputation {
+        self.try_compute_swap(zero_for_one, amount_in)
+            .unwrap_or_else(|error| error.panic())
+    }
+
+    /// like `compute_swap` but return the error instead of panicking
+    fn try_compute_swap(
+        &self,
+        zero_for_one: bool,
+        amount_in: Balance,
+    ) -> Result<SwapComputation, DeFiError> {
+        let in_idx = if zero_for_one { 0 } else { 1 };
+        let mut state = SwapComputation {
+            amount_out: 0,
+            sqrt_price: self.sqrt_price,
+            current_tick: self.current_tick,
+            liquidity: self.liquidity,
+            fee_growth_global: self.fee_growth_global[in_idx],
+            crossed_ticks: vec![],
+        };
+        let fee_divisor = U256::from(FEE_DIVISOR);
+        let fee_rest = U256::from(FEE_DIVISOR - self.total_fee);
+        let mut remaining = amount_in;
+        while remaining > 0 {
+            let next_tick = if zero_for_one {
+                self.ticks.floor_key(&state.current_tick)
+            } else {
+                self.ticks.higher(&state.current_tick)
+            };
+            let target_tick = next_tick.unwrap_or(if zero_for_one { MIN_TICK } else { MAX_TICK });
+            let target = sqrt_price_at_tick(target_tick);
+            check(
+                next_tick.is_some() || target != state.sqrt_price,
+                DeFiError::NotEnoughLiquidity,
+            )?;
+
+            let (sqrt_price, used, gross) = if state.liquidity == 0 {
+                (target, 0, 0)
+            } else {
+                let net = (U256::from(remaining) * fee_rest / fee_divisor).as_u128();
+                let to_target = if zero_for_one {
+                    amount0_delta(target, state.sqrt_price, state.liquidity, true)
+                } else {
+                    amount1_delta(state.sqrt_price, target, state.liquidity, true)
+                };
+                if U256::from(net) >= to_target {
+                    let used = to_target.as_u128();
+                    let gross = div_round_up(U256::from(used) * fee_divisor, fee_rest).as_u128();
+                    (target, used, gross.min(remaining))
+                } else if zero_for_one {
+                    // sqrt_price' = L / (L / sqrt_price + amount), rounded up
+                    let numerator = U256::from(state.liquidity) << 64;
+                    let denominator = numerator / U256::from(state.sqrt_price) + U256::from(net);
+                    let next = div_round_up(numerator, denominator).as_u128();
+                    (next.clamp(target, state.sqrt_price), net, remaining)
+                } else {
+                    // sqrt_price' = sqrt_price + amount / L, rounded down
+                    let delta = (U256::from(net) << 64) / U256::from(state.liquidity);
+                    let next = (U256::from(state.sqrt_price) + delta).as_u128();
+                    (next.clamp(state.sqrt_price, target), net, remaining)
+                }
+            };
+            let amount_out = if zero_for_one {
+                amount1_delta(sqrt_price, state.sqrt_price, state.liquidity, false)
+            } else {
+                amount0_delta(state.sqrt_price, sqrt_price, state.liquidity, false)
+            };
+            state.amount_out = state
+                .amount_out
+                .checked_add(to_balance(amount_out))
+                .unwrap_or_else(|| DeFiError::AmountTooLarge.panic());
+            remaining -= gross;
+            if state.liquidity > 0 {
+                let growth = (U256::from(gross - used) << 64) / U256::from(state.liquidity);
+                state.fee_growth_global = state.fee_growth_global.wrapping_add(growth.low_u128());
+            }
+            state.sqrt_price = sqrt_price;
+
+            if sqrt_price != target {
+                state.current_tick = tick_at_sqrt_price(sqrt_price);
+            } else if let Some(tick) = next_tick {
+                let liquidity_net = self.ticks.get(&tick).unwrap_or_default().liquidity_net;
+                state.liquidity = if zero_for_one {
+                    add_liquidity_delta(state.liquidity, -liquidity_net)
+                } else {
+                    add_liquidity_delta(state.liquidity, liquidity_net)
+                };
+                state.crossed_ticks.push((tick, state.fee_growth_global));
+                state.current_tick = if zero_for_one { tick - 1 } else { tick };
+            } else {
+                // the price reached the end of the tick range
+                check(remaining == 0, DeFiError::NotEnoughLiquidity)?;
+                state.current_tick = target_tick;
+            }
+        }
+        Ok(state)
+    }
+
+    /// how many `token_out` would be returned for `amount_in` of `token_in`
+    pub fn get_return(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+    ) -> Balance {
+        self.try_get_return(token_in, amount_in, token_out)
+            .unwrap_or_else(|error| error.panic())
+    }
+
+    /// like `get_return` but return the error instead of panicking
+    pub fn try_get_return(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+    ) -> Result<Balance, DeFiError> {
+        let in_idx =
+            token_index(&self.token_account_ids, token_in).ok_or(DeFiError::UnknownToken)?;
+        let out_idx =
+            token_index(&self.token_account_ids, token_out).ok_or(DeFiError::UnknownToken)?;
+        check(in_idx != out_idx, DeFiError::SameToken)?;
+        Ok(self.try_compute_swap(in_idx == 0, amount_in)?.amount_out)
+    }
+
+    /// swap `amount_in` of `token_in` into the pool, return the amount of `token_out`
+    pub fn swap(
+        &mut self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+    ) -> Balance {
+        ensure(amount_in > 0, DeFiError::ZeroAmount);
+        let in_idx = assert_token_index(&self.token_account_ids, token_in);
+        let out_idx = assert_token_index(&self.token_account_ids, token_out);
+        ensure(in_idx != out_idx, DeFiError::SameToken);
+        let state = self.compute_swap(in_idx == 0, amount_in);
+        ensure(state.amount_out > 0, DeFiError::ZeroAmount);
+        for (tick, fee_growth_global) in state.crossed_ticks {
+            let mut info = self.ticks.get(&tick).unwrap_or_default();
+            info.fee_growth_outside[in_idx] =
+                fee_growth_global.wrapping_sub(info.fee_growth_outside[in_idx]);
+            info.fee_growth_outside[out_idx] =
+                self.fee_growth_global[out_idx].wrapping_sub(info.fee_growth_outside[out_idx]);
+            self.ticks.insert(&tick, &info);
+        }
+        self.sqrt_price = state.sqrt_price;
+        self.current_tick = state.current_tick;
+        self.liquidity = state.liquidity;
+        self.fee_growth_global[in_idx] = state.fee_growth_global;
+        self.amounts[in_idx] = add_reserve(self.amounts[in_idx], amount_in);
+        self.amounts[out_idx] -= state.amount_out;
+        state.amount_out
+    }
+
+    /// move of the price of `token_in` in `token_out` caused by swapping `amount_in`,
+    /// in basis points
+    pub fn price_impact(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+    ) -> u32 {
+        let in_idx = assert_token_index(&self.token_account_ids, token_in);
+        assert_token_index(&self.token_account_ids, token_out);
+        let sqrt_price = self.compute_swap(in_idx == 0, amount_in).sqrt_price;
+        let before = U256::from(self.sqrt_price) * U256::from(self.sqrt_price);
+        let after = U256::from(sqrt_price) * U256::from(sqrt_price);
+        // the price of token 1 is the inverse of the pool price
+        if in_idx == 0 {
+            price_move(before, after)
+        } else {
+            price_move(after, before)
+        }
+    }
+}
+
+#[near_bindgen]
+impl DeFi {
+    /// create a concentrated liquidity pool of two whitelisted tokens starting at the price
+    /// of `initial_tick`, positions use multiples of `tick_spacing`. the attached deposit
+    /// pays for its storage and the rest is refunded. return the id of the pool
+    #[payable]
+    pub fn add_concentrated_pool(
+        &mut self,
+        token_ids: Vec<AccountId>,
+        total_fee: u32,
+        tick_spacing: u32,
+        initial_tick: i32,
+    ) -> u64 {
+        ensure(
+            token_ids.len() == 2 && token_ids[0] != token_ids[1],
+            DeFiError::InvalidPoolTokens,
+        );
+        for token_id in token_ids.iter() {
+            self.assert_whitelisted(token_id);
+        }
+        let pool_id = self.pools.len();
+        self.internal_add_pool(Pool::ConcentratedPool(ConcentratedPool::new(
+            pool_id,
+            token_ids,
+            total_fee,
+            tick_spacing,
+            initial_tick,
+        )))
+    }
+
+    /// open a position between `lower_tick` and `upper_tick` in the pool `pool_id` with at
+    /// most `amounts` of the caller's internal balances. panics if the block timestamp is
+    /// past `deadline` (nanoseconds). return the id of the position
+    pub fn add_position(
+        &mut self,
+        pool_id: u64,
+        lower_tick: i32,
+        upper_tick: i32,
+        amounts: Vec<U128>,
+        deadline: Option<U64>,
+    ) -> U64 {
+        ensure(!self.paused.add_liquidity, DeFiError::AddLiquidityPaused);
+        assert_deadline(deadline);
+        let account_id = env::predecessor_account_id();
+        let prev_storage = env::storage_usage();
+        let mut amounts: Vec<Balance> = amounts.into_iter().map(|a| a.0).collect();
+        let mut pool = self.internal_get_concentrated_pool(pool_id);
+        let (position_id, liquidity) =
+            pool.add_position(&account_id, lower_tick, upper_tick, &mut amounts);
+        let token_ids = pool.token_account_ids.clone();
+        self.pools.replace(pool_id, &Pool::ConcentratedPool(pool));
+        for (token_id, amount) in token_ids.iter().zip(amounts.iter()) {
+            self.internal_withdraw(&account_id, token_id, *amount);
+        }
+        self.internal_set_joined_pool(&account_id, pool_id, true);
+        self.internal_update_storage(&account_id, prev_storage);
+        AddPosition {
+            pool_id,
+            position_id: &U64(position_id),
+            account_id: &account_id,
+            lower_tick,
+            upper_tick,
+            liquidity: &U128(liquidity),
+            amounts: &amounts.into_iter().map(U128).collect::<Vec<_>>(),
+        }
+        .emit();
+        U64(position_id)
+    }
+
+    /// take `liquidity` out of the caller's position, the tokens and the fees earned are
+    /// credited to the internal balances and the position is closed once it is empty.
+    /// panics if the block timestamp is past `deadline` (nanoseconds)
+    pub fn remove_position(
+        &mut self,
+        pool_id: u64,
+        position_id: U64,
+        liquidity: U128,
+        min_amounts: Vec<U128>,
+        deadline: Option<U64>,
+    ) -> Vec<U128> {
+        ensure(!self.paused.withdraw, DeFiError::WithdrawPaused);
+        assert_deadline(deadline);
+        let account_id = env::predecessor_account_id();
+        let prev_storage = env::storage_usage();
+        let min_amounts: Vec<Balance> = min_amounts.into_iter().map(|a| a.0).collect();
+        let mut pool = self.internal_get_concentrated_pool(pool_id);
+        let (amounts, fees) =
+            pool.remove_position(&account_id, position_id.0, liquidity.0, &min_amounts);
+        let token_ids = pool.token_account_ids.clone();
+        let joined = pool.share_balance_of(&account_id) > 0;
+        self.pools.replace(pool_id, &Pool::ConcentratedPool(pool));
+        for ((token_id, amount), fee) in token_ids.iter().zip(amounts).zip(fees) {
+            self.internal_deposit(&account_id, token_id, amount + fee);
+        }
+        self.internal_set_joined_pool(&account_id, pool_id, joined);
+        self.internal_update_storage(&account_id, prev_storage);
+        let amounts: Vec<U128> = amounts.into_iter().map(U128).collect();
+        RemovePosition {
+            pool_id,
+            position_id: &position_id,
+            account_id: &account_id,
+            liquidity: &liquidity,
+            amounts: &amounts,
+            fees: &fees.into_iter().map(U128).collect::<Vec<_>>(),
+        }
+        .emit();
+        amounts
+    }
+
+    /// credit the fees earned by the caller's position to the internal balances
+    pub fn claim_position_fees(&mut self, pool_id: u64, position_id: U64) -> Vec<U128> {
+        ensure(!self.paused.withdraw, DeFiError::WithdrawPaused);
+        let account_id = env::predecessor_account_id();
+        let prev_storage = env::storage_usage();
+        let mut pool = self.internal_get_concentrated_pool(pool_id);
+        let fees = pool.claim_fees(&account_id, position_id.0);
+        let token_ids = pool.token_account_ids.clone();
+        self.pools.replace(pool_id, &Pool::ConcentratedPool(pool));
+        for (token_id, fee) in token_ids.iter().zip(fees) {
+            self.internal_deposit(&account_id, token_id, fee);
+        }
+        self.internal_update_storage(&account_id, prev_storage);
+        let fees: Vec<U128> = fees.into_iter().map(U128).collect();
+        ClaimPositionFees {
+            pool_id,
+            position_id: &position_id,
+            account_id: &account_id,
+            amounts: &fees,
+        }
+        .emit();
+        fees
+    }
+
+    pub fn get_position(&self, pool_id: u64, position_id: U64) -> Option<PositionInfo> {
+        self.internal_get_concentrated_pool(pool_id)
+            .position_info(position_id.0)
+    }
+
+    pub fn get_account_positions(&self, pool_id: u64, account_id: AccountId) -> Vec<PositionInfo> {
+        self.internal_get_concentrated_pool(pool_id)
+            .positions_of(&account_id)
+    }
+}
+
+impl DeFi {
+    fn internal_get_concentrated_pool(&self, pool_id: u64) -> ConcentratedPool {
+        match self.internal_get_pool(pool_id) {
+            Pool::ConcentratedPool(pool) => pool,
+            _ => DeFiError::NotConcentratedPool.panic(),
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::accounts;
+    use near_sdk::testing_env;
+
+    use super::*;
+
+    fn pool(total_fee: u32) -> ConcentratedPool {
+        testing_env!(near_sdk::test_utils::VMContextBuilder::new().build());
+        ConcentratedPool::new(0, vec![accounts(3), accounts(4)], total_fee, 10, 0)
+    }
+
+    #[test]
+    fn tick_math() {
+        assert_eq!(sqrt_price_at_tick(0), 1 << 64);
+        let price = |tick: i32| (sqrt_price_at_tick(tick) as f64 / (1u128 << 64) as f64).powi(2);
+        assert!((price(1000) / 1.0001f64.powi(1000) - 1.0).abs() < 1e-12);
+        assert!((price(-20_000) / 1.0001f64.powi(-20_000) - 1.0).abs() < 1e-12);
+        for tick in [MIN_TICK, -1, 0, 1, 12_345, MAX_TICK] {
+            assert_eq!(tick_at_sqrt_price(sqrt_price_at_tick(tick)), tick);
+        }
+        assert_eq!(tick_at_sqrt_price(sqrt_price_at_tick(100) - 1), 99);
+    }
+
+    #[test]
+    fn in_range_position_swaps_like_constant_product() {
+        let mut pool = pool(0);
+        let mut amounts = [1_000_000, 1_000_000];
+        let (_, liquidity) =
+            pool.add_position(&accounts(0), MIN_TICK + 6, MAX_TICK - 6, &mut amounts);
+        assert!(amounts[0] <= 1_000_000 && amounts[0] > 999_990);
+        assert_eq!(pool.liquidity, liquidity);
+
+        let amount_out = pool.swap(&accounts(3), 1_000, &accounts(4));
+        assert!((998..=999).contains(&amount_out));
+        assert!(pool.current_tick < 0);
+        let amount_back = pool.swap(&accounts(4), amount_out, &accounts(3));
+        assert!(amount_back <= 1_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "E066: can't swap a token for itself")]
+    fn self_swap_rejected() {
+        let mut pool = pool(0);
+        pool.add_position(&accounts(0), -1000, 1000, &mut [1_000_000, 1_000_000]);
+        assert_eq!(
+            pool.try_get_return(&accounts(3), 1_000, &accounts(3)),
+            Err(DeFiError::SameToken)
+        );
+        pool.swap(&accounts(3), 1_000, &accounts(3));
+    }
+
+    #[test]
+    fn narrow_range_gives_more_liquidity() {
+        let mut pool = pool(0);
+        let (_, wide) = pool.add_position(&accounts(0), -1000, 1000, &mut [1_000_000, 1_000_000]);
+        let (_, narrow) = pool.add_position(&accounts(1), -100, 100, &mut [1_000_000, 1_000_000]);
+        assert!(narrow > wide * 9);
+        assert_eq!(pool.liquidity, wide + narrow);
+        assert_eq!(pool.share_balance_of(&accounts(1)), narrow);
+    }
+
+    #[test]
+    fn out_of_range_position_holds_one_token() {
+        let mut pool = pool(0);
+        let mut amounts = [1_000_000, 1_000_000];
+        pool.add_position(&accounts(0), 100, 200, &mut amounts);
+        assert_eq!(amounts[1], 0);
+        assert!(amounts[0] > 0);
+        assert_eq!(pool.liquidity, 0);
+    }
+
+    #[test]
+    fn swap_crosses_ticks_and_shares_fees_in_range() {
+        let mut pool = pool(100);
+        let (wide_id, _) = pool.add_position(&accounts(0), -1000, 1000, &mut [100_000, 100_000]);
+        let (narrow_id, narrow) = pool.add_position(&accounts(1), -10, 10, &mut [100_000, 100_000]);
+
+        // sell enough token 0 to leave the narrow range
+        let amount_out = pool.swap(&accounts(3), 200_000, &accounts(4));
+        assert!(amount_out > 0);
+        assert!(pool.current_tick < -10);
+        assert_eq!(pool.liquidity, pool.total_liquidity - narrow);
+
+        let wide_fees = pool.claim_fees(&accounts(0), wide_id);
+        let narrow_fees = pool.claim_fees(&accounts(1), narrow_id);
+        assert_eq!(wide_fees[1], 0);
+        assert!(wide_fees[0] > 0 && narrow_fees[0] > 0);
+        // 1% of the input, less rounding
+        assert!(wide_fees[0] + narrow_fees[0] <= 2_000);
+        assert!(wide_fees[0] + narrow_fees[0] >= 1_990);
+
+        // a second claim finds nothing new
+        assert_eq!(pool.claim_fees(&accounts(1), narrow_id), [0, 0]);
+
+        // crossing back into the narrow range activates it again
+        pool.swap(&accounts(4), 200_000, &accounts(3));
+        assert!(pool.current_tick >= -10);
+        assert!(pool.claim_fees(&accounts(1), narrow_id)[1] > 0);
+    }
+
+    #[test]
+    fn remove_position_returns_tokens() {
+        let mut pool = pool(0);
+        let mut amounts = [100_000, 100_000];
+        let (position_id, liquidity) = pool.add_position(&accounts(0), -100, 100, &mut amounts);
+        let (removed, fees) = pool.remove_position(&accounts(0), position_id, liquidity, &[0, 0]);
+        assert!(removed[0] + 2 >= amounts[0] && removed[0] <= amounts[0]);
+        assert!(removed[1] + 2 >= amounts[1] && removed[1] <= amounts[1]);
+        assert_eq!(fees, [0, 0]);
+        assert_eq!(pool.liquidity, 0);
+        assert!(pool.position_info(position_id).is_none());
+        assert!(pool.positions_of(&accounts(0)).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "E047: not enough liquidity")]
+    fn swap_beyond_liquidity() {
+        let mut pool = pool(0);
+        pool.add_position(&accounts(0), 0, 100, &mut [1_000, 1_000]);
+        pool.swap(&accounts(4), 1_000_000, &accounts(3));
+    }
+
+    #[test]
+    #[should_panic(expected = "E048: invalid tick range")]
+    fn tick_not_on_spacing() {
+        pool(0).add_position(&accounts(0), -15, 100, &mut [1_000, 1_000]);
+    }
+
+    #[test]
+    #[should_panic(expected = "E052: not the owner of the position")]
+    fn remove_position_of_other_account() {
+        let mut pool = pool(0);
+        let (position_id, liquidity) =
+            pool.add_position(&accounts(0), -100, 100, &mut [1_000, 1_000]);
+        pool.remove_position(&accounts(1), position_id, liquidity, &[0, 0]);
+    }
+}
@@ -0,0 +1,292 @@
+//! Errors of the DeFi contract. A failed call panics with `E<code>: <message>`,
+//! e.g. `E009: fee is too large`, the codes never change once released and
+//! the codes of removed errors are not reused.
+
+use std::fmt;
+
+use near_sdk::FunctionError;
+
+use crate::Role;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeFiError {
+    AlreadyInitialized,
+    /// the symbol is neither TokenA nor TokenB
+    UnknownSymbol,
+    /// `set_token_info` hasn't stored the token yet
+    TokenInfoNotFound,
+    /// tokens are sent by a contract which isn't one of the pool tokens
+    UnknownToken,
+    MissingRole(Role),
+    OwnerRoleNotGrantable,
+    NoPendingOwner,
+    NotPendingOwner,
+    FeeTooLarge,
+    /// the prepaid gas, at least the given Tgas is required
+    NotEnoughGas(u64),
+    AddLiquidityPaused,
+    WithdrawPaused,
+    RatioTooLarge,
+    ReserveOverflow,
+    AmountTooLarge,
+    NoCodeToDeploy,
+    StateNotFound,
+    UnknownStateVersion,
+    /// the internal balance is lower than the amount
+    NotEnoughDeposit,
+    /// `ft_transfer_call` only accepts an empty `msg`
+    InvalidMessage,
+    NotEnoughShares,
+    /// the tokens returned by `remove_liquidity` are less than `min_amounts`
+    MinAmountNotReached,
+    EmptyPool,
+    /// the amounts don't match the tokens of the pool
+    InvalidAmounts,
+    ZeroAmount,
+    /// `storage_deposit` hasn't been called for the account
+    AccountNotRegistered,
+    NotEnoughStorageDeposit,
+    /// the account still has internal balances, LP shares or limit orders
+    AccountNotEmpty,
+    TokenNotWhitelisted,
+    PoolNotFound,
+    /// a simple pool needs two different tokens, a StableSwap pool up to four
+    /// with their decimals and a weighted pool up to eight
+    InvalidPoolTokens,
+    /// `get_required_gas` only knows the methods which call token contracts
+    UnknownMethod,
+    /// the gas of every step must be positive
+    InvalidGasConfig,
+    /// no observation is as old as the start of the window
+    NotEnoughPriceHistory,
+    InvalidTwapWindow,
+    /// the block timestamp is past the `deadline` of the call
+    DeadlinePassed,
+    InvalidAmp,
+    /// a ramp lasts at least a day and changes the amp factor at most 10 times
+    InvalidRamp,
+    NotStableSwapPool,
+    /// Newton's method of the StableSwap invariant didn't converge
+    InvariantNotConverged,
+    /// weights are at least 1% each and add up to 100%
+    InvalidWeights,
+    /// a weighted pool swap adds at most half of the reserve of `token_in`
+    MaxInRatioExceeded,
+    /// the liquidity in range can't take the whole swap or position
+    NotEnoughLiquidity,
+    /// ticks are within +-443636, multiples of the tick spacing and the lower one is below
+    InvalidTickRange,
+    NotConcentratedPool,
+    /// a concentrated liquidity pool has positions instead of shares
+    SharesNotSupported,
+    PositionNotFound,
+    NotPositionOwner,
+    OrderNotFound,
+    NotOrderOwner,
+    SwapPaused,
+    /// the account can't withdraw or start another flash swap until its flash swap is paid back
+    FlashSwapInProgress,
+    /// a concentrated liquidity pool can't lend its reserves
+    FlashSwapNotSupported,
+    NoFlashSwap,
+    WNearNotConfigured,
+    NearWrapFailed,
+    /// a window limit without a window length
+    InvalidCircuitBreaker,
+    /// the swap at the given index of an all-or-nothing batch was rejected
+    BatchSwapRejected(usize),
+    /// a flash swap would move the price more than the circuit breaker allows
+    PriceMoveTooLarge,
+    /// the swapper named itself as the referrer
+    SelfReferral,
+    /// an oracle observation past the recorded ones was read
+    ObservationNotFound,
+    /// `token_in` and `token_out` of a swap are the same token
+    SameToken,
+}
+
+impl DeFiError {
+    pub fn code(&self) -> u32 {
+        match self {
+            DeFiError::AlreadyInitialized => 1,
+            DeFiError::UnknownSymbol => 2,
+            DeFiError::TokenInfoNotFound => 3,
+            DeFiError::UnknownToken => 4,
+            DeFiError::MissingRole(_) => 5,
+            DeFiError::OwnerRoleNotGrantable => 6,
+            DeFiError::NoPendingOwner => 7,
+            DeFiError::NotPendingOwner => 8,
+            DeFiError::FeeTooLarge => 9,
+            DeFiError::NotEnoughGas(_) => 10,
+            DeFiError::AddLiquidityPaused => 11,
+            DeFiError::WithdrawPaused => 12,
+            DeFiError::RatioTooLarge => 16,
+            DeFiError::ReserveOverflow => 18,
+            DeFiError::AmountTooLarge => 19,
+            DeFiError::NoCodeToDeploy => 20,
+            DeFiError::StateNotFound => 21,
+            DeFiError::UnknownStateVersion => 22,
+            DeFiError::NotEnoughDeposit => 23,
+            DeFiError::InvalidMessage => 24,
+            DeFiError::NotEnoughShares => 25,
+            DeFiError::MinAmountNotReached => 26,
+            DeFiError::EmptyPool => 27,
+            DeFiError::InvalidAmounts => 28,
+            DeFiError::ZeroAmount => 29,
+            DeFiError::AccountNotRegistered => 30,
+            DeFiError::NotEnoughStorageDeposit => 31,
+            DeFiError::AccountNotEmpty => 32,
+            DeFiError::TokenNotWhitelisted => 33,
+            DeFiError::PoolNotFound => 34,
+            DeFiError::InvalidPoolTokens => 35,
+            DeFiError::UnknownMethod => 36,
+            DeFiError::InvalidGasConfig => 37,
+            DeFiError::NotEnoughPriceHistory => 38,
+            DeFiError::InvalidTwapWindow => 39,
+            DeFiError::DeadlinePassed => 40,
+            DeFiError::InvalidAmp => 41,
+            DeFiError::InvalidRamp => 42,
+            DeFiError::NotStableSwapPool => 43,
+            DeFiError::InvariantNotConverged => 44,
+            DeFiError::InvalidWeights => 45,
+            DeFiError::MaxInRatioExceeded => 46,
+            DeFiError::NotEnoughLiquidity => 47,
+            DeFiError::InvalidTickRange => 48,
+            DeFiError::NotConcentratedPool => 49,
+            DeFiError::SharesNotSupported => 50,
+            DeFiError::PositionNotFound => 51,
+            DeFiError::NotPositionOwner => 52,
+            DeFiError::OrderNotFound => 53,
+            DeFiError::NotOrderOwner => 54,
+            DeFiError::SwapPaused => 55,
+            DeFiError::FlashSwapInProgress => 56,
+            DeFiError::FlashSwapNotSupported => 57,
+            DeFiError::NoFlashSwap => 58,
+            DeFiError::WNearNotConfigured => 59,
+            DeFiError::NearWrapFailed => 60,
+            DeFiError::InvalidCircuitBreaker => 61,
+            DeFiError::BatchSwapRejected(_) => 62,
+            DeFiError::PriceMoveTooLarge => 63,
+            DeFiError::SelfReferral => 64,
+            DeFiError::ObservationNotFound => 65,
+            DeFiError::SameToken => 66,
+        }
+    }
+}
+
+impl fmt::Display for DeFiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "E{:03}: ", self.code())?;
+        match self {
+            DeFiError::AlreadyInitialized => write!(f, "already initialized"),
+            DeFiError::UnknownSymbol => write!(f, "only support TokenA and TokenB"),
+            DeFiError::TokenInfoNotFound => write!(f, "token info not found"),
+            DeFiError::UnknownToken => write!(f, "unknown token"),
+            DeFiError::MissingRole(role) => write!(f, "{:?} role required", role),
+            DeFiError::OwnerRoleNotGrantable => write!(f, "owner role can't be granted"),
+            DeFiError::NoPendingOwner => write!(f, "no pending owner"),
+            DeFiError::NotPendingOwner => {
+                write!(f, "only the pending owner can accept the ownership")
+            }
+            DeFiError::FeeTooLarge => write!(f, "fee is too large"),
+            DeFiError::NotEnoughGas(tgas) => {
+                write!(f, "not enough gas, at least {} Tgas is required", tgas)
+            }
+            DeFiError::AddLiquidityPaused => write!(f, "add liquidity is paused"),
+            DeFiError::WithdrawPaused => write!(f, "withdraw is paused"),
+            DeFiError::RatioTooLarge => write!(f, "ratio is too large"),
+            DeFiError::ReserveOverflow => write!(f, "reserve overflow"),
+            DeFiError::AmountTooLarge => write!(f, "amount is too large"),
+            DeFiError::NoCodeToDeploy => write!(f, "no code to deploy"),
+            DeFiError::StateNotFound => write!(f, "state not found"),
+            DeFiError::UnknownStateVersion => write!(f, "unknown state version"),
+            DeFiError::NotEnoughDeposit => write!(f, "not enough deposit"),
+            DeFiError::InvalidMessage => write!(f, "invalid message"),
+            DeFiError::NotEnoughShares => write!(f, "not enough shares"),
+            DeFiError::MinAmountNotReached => write!(f, "min amount not reached"),
+            DeFiError::EmptyPool => write!(f, "pool has no liquidity"),
+            DeFiError::InvalidAmounts => write!(f, "wrong number of amounts"),
+            DeFiError::ZeroAmount => write!(f, "amount must be positive"),
+            DeFiError::AccountNotRegistered => write!(f, "account not registered"),
+            DeFiError::NotEnoughStorageDeposit => write!(f, "not enough storage deposit"),
+            DeFiError::AccountNotEmpty => write!(f, "account has balances or shares"),
+            DeFiError::TokenNotWhitelisted => write!(f, "token not whitelisted"),
+            DeFiError::PoolNotFound => write!(f, "pool not found"),
+            DeFiError::InvalidPoolTokens => write!(f, "invalid pool tokens"),
+            DeFiError::UnknownMethod => write!(f, "unknown method"),
+            DeFiError::InvalidGasConfig => write!(f, "invalid gas config"),
+            DeFiError::NotEnoughPriceHistory => write!(f, "not enough price history"),
+            DeFiError::InvalidTwapWindow => write!(f, "window must be positive"),
+            DeFiError::DeadlinePassed => write!(f, "deadline has passed"),
+            DeFiError::InvalidAmp => write!(f, "invalid amp factor"),
+            DeFiError::InvalidRamp => write!(f, "invalid amp ramp"),
+            DeFiError::NotStableSwapPool => write!(f, "not a stable swap pool"),
+            DeFiError::InvariantNotConverged => write!(f, "invariant not converged"),
+            DeFiError::InvalidWeights => write!(f, "invalid weights"),
+            DeFiError::MaxInRatioExceeded => write!(f, "amount exceeds the max in ratio"),
+            DeFiError::NotEnoughLiquidity => write!(f, "not enough liquidity"),
+            DeFiError::InvalidTickRange => write!(f, "invalid tick range"),
+            DeFiError::NotConcentratedPool => write!(f, "not a concentrated liquidity pool"),
+            DeFiError::SharesNotSupported => write!(f, "pool has positions instead of shares"),
+            DeFiError::PositionNotFound => write!(f, "position not found"),
+            DeFiError::NotPositionOwner => write!(f, "not the owner of the position"),
+            DeFiError::OrderNotFound => write!(f, "order not found"),
+            DeFiError::NotOrderOwner => write!(f, "not the owner of the order"),
+            DeFiError::SwapPaused => write!(f, "swap is paused"),
+            DeFiError::FlashSwapInProgress => write!(f, "flash swap in progress"),
+            DeFiError::FlashSwapNotSupported => write!(f, "pool can't lend its reserves"),
+            DeFiError::NoFlashSwap => write!(f, "no flash swap to repay"),
+            DeFiError::WNearNotConfigured => write!(f, "wNEAR contract is not set"),
+            DeFiError::NearWrapFailed => write!(f, "wrapping NEAR failed"),
+            DeFiError::InvalidCircuitBreaker => write!(f, "window move limit needs window blocks"),
+            DeFiError::BatchSwapRejected(index) => {
+                write!(f, "swap {} of the batch was rejected", index)
+            }
+            DeFiError::PriceMoveTooLarge => write!(f, "price move is too large"),
+            DeFiError::SelfReferral => write!(f, "can't refer own swaps"),
+            DeFiError::ObservationNotFound => write!(f, "price observation not found"),
+            DeFiError::SameToken => write!(f, "can't swap a token for itself"),
+        }
+    }
+}
+
+impl FunctionError for DeFiError {
+    fn panic(&self) -> ! {
+        // the panic hook of near_bindgen reports it like `env::panic_str`
+        panic!("{}", self)
+    }
+}
+
+/// panic with `error` unless `condition` holds
+pub(crate) fn ensure(condition: bool, error: DeFiError) {
+    if !condition {
+        error.panic()
+    }
+}
+
+/// like `ensure` but return `error` instead of panicking
+pub(crate) fn check(condition: bool, error: DeFiError) -> Result<(), DeFiError> {
+    if condition {
+        Ok(())
+    } else {
+        Err(error)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_message() {
+        assert_eq!(DeFiError::FeeTooLarge.to_string(), "E009: fee is too large");
+        assert_eq!(
+            DeFiError::MissingRole(Role::Pauser).to_string(),
+            "E005: Pauser role required"
+        );
+        assert_eq!(
+            DeFiError::NotEnoughGas(35).to_string(),
+            "E010: not enough gas, at least 35 Tgas is required"
+        );
+    }
+}
@@ -0,0 +1,531 @@
+//! NEP-297 events of the DeFi contract, logged as `EVENT_JSON:{...}` with the
+//! "simple-swap" standard so indexers can pick them up.
+//!
+//! <https://github.com/near/NEPs/blob/master/specs/Standards/EventsFormat.md>
+
+use std::collections::HashMap;
+
+use near_sdk::json_types::{U128, U64};
+use near_sdk::{env, serde_json, AccountId};
+use serde::Serialize;
+
+use crate::{CircuitBreaker, GasConfig, PauseFlags};
+
+pub const EVENT_STANDARD: &str = "simple-swap";
+pub const EVENT_STANDARD_VERSION: &str = "1.0.0";
+
+/// Data to log for a completed swap.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+pub struct Swap<'a> {
+    pub swap_id: &'a U64,
+    pub pool_id: u64,
+    pub account_id: &'a AccountId,
+    pub token_in: &'a AccountId,
+    pub amount_in: &'a U128,
+    pub token_out: &'a AccountId,
+    pub amount_out: &'a U128,
+    pub fee: &'a U128,
+}
+
+impl Swap<'_> {
+    pub fn emit(self) {
+        new_event(SimpleSwapEventKind::Swap(&[self])).emit()
+    }
+}
+
+/// Data to log when tokens are deposited to the internal balance of `account_id`.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+pub struct Deposit<'a> {
+    pub account_id: &'a AccountId,
+    pub token_id: &'a AccountId,
+    pub amount: &'a U128,
+}
+
+impl Deposit<'_> {
+    pub fn emit(self) {
+        new_event(SimpleSwapEventKind::Deposit(&[self])).emit()
+    }
+}
+
+/// Data to log when tokens are sent back from the internal balance of `account_id`.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+pub struct Withdraw<'a> {
+    pub account_id: &'a AccountId,
+    pub token_id: &'a AccountId,
+    pub amount: &'a U128,
+}
+
+impl Withdraw<'_> {
+    pub fn emit(self) {
+        new_event(SimpleSwapEventKind::Withdraw(&[self])).emit()
+    }
+}
+
+/// Data to log when tokens are added to the pool for `shares`.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+pub struct AddLiquidity<'a> {
+    pub pool_id: u64,
+    pub account_id: &'a AccountId,
+    pub token_ids: &'a [AccountId],
+    pub amounts: &'a [U128],
+    pub shares: &'a U128,
+}
+
+impl AddLiquidity<'_> {
+    pub fn emit(self) {
+        new_event(SimpleSwapEventKind::AddLiquidity(&[self])).emit()
+    }
+}
+
+/// Data to log when `shares` are burned for tokens of the pool.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+pub struct RemoveLiquidity<'a> {
+    pub pool_id: u64,
+    pub account_id: &'a AccountId,
+    pub token_ids: &'a [AccountId],
+    pub amounts: &'a [U128],
+    pub shares: &'a U128,
+}
+
+impl RemoveLiquidity<'_> {
+    pub fn emit(self) {
+        new_event(SimpleSwapEventKind::RemoveLiquidity(&[self])).emit()
+    }
+}
+
+/// Data to log when a concentrated liquidity position is opened with `amounts`.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+pub struct AddPosition<'a> {
+    pub pool_id: u64,
+    pub position_id: &'a U64,
+    pub account_id: &'a AccountId,
+    pub lower_tick: i32,
+    pub upper_tick: i32,
+    pub liquidity: &'a U128,
+    pub amounts: &'a [U128],
+}
+
+impl AddPosition<'_> {
+    pub fn emit(self) {
+        new_event(SimpleSwapEventKind::AddPosition(&[self])).emit()
+    }
+}
+
+/// Data to log when `liquidity` is taken out of a position for `amounts` and its `fees`.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+pub struct RemovePosition<'a> {
+    pub pool_id: u64,
+    pub position_id: &'a U64,
+    pub account_id: &'a AccountId,
+    pub liquidity: &'a U128,
+    pub amounts: &'a [U128],
+    pub fees: &'a [U128],
+}
+
+impl RemovePosition<'_> {
+    pub fn emit(self) {
+        new_event(SimpleSwapEventKind::RemovePosition(&[self])).emit()
+    }
+}
+
+/// Data to log when the fees earned by a position are claimed.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+pub struct ClaimPositionFees<'a> {
+    pub pool_id: u64,
+    pub position_id: &'a U64,
+    pub account_id: &'a AccountId,
+    pub amounts: &'a [U128],
+}
+
+impl ClaimPositionFees<'_> {
+    pub fn emit(self) {
+        new_event(SimpleSwapEventKind::ClaimPositionFees(&[self])).emit()
+    }
+}
+
+/// Data to log when `account_id` escrows `amount_in` in a limit order.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+pub struct LimitOrderPlaced<'a> {
+    pub order_id: &'a U64,
+    pub account_id: &'a AccountId,
+    pub pool_id: u64,
+    pub token_in: &'a AccountId,
+    pub amount_in: &'a U128,
+    pub token_out: &'a AccountId,
+    pub min_amount_out: &'a U128,
+}
+
+impl LimitOrderPlaced<'_> {
+    pub fn emit(self) {
+        new_event(SimpleSwapEventKind::LimitOrderPlaced(&[self])).emit()
+    }
+}
+
+/// Data to log when a limit order is cancelled and `amount_in` is credited back.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+pub struct LimitOrderCancelled<'a> {
+    pub order_id: &'a U64,
+    pub account_id: &'a AccountId,
+    pub amount_in: &'a U128,
+}
+
+impl LimitOrderCancelled<'_> {
+    pub fn emit(self) {
+        new_event(SimpleSwapEventKind::LimitOrderCancelled(&[self])).emit()
+    }
+}
+
+/// Data to log when `amount_in` of a limit order of `account_id` is sold for `amount_out`,
+/// by a taker or against the pool when `taker_id` is missing.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+pub struct LimitOrderFilled<'a> {
+    pub order_id: &'a U64,
+    pub account_id: &'a AccountId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub taker_id: Option<&'a AccountId>,
+    pub amount_in: &'a U128,
+    pub amount_out: &'a U128,
+}
+
+impl LimitOrderFilled<'_> {
+    pub fn emit(self) {
+        new_event(SimpleSwapEventKind::LimitOrderFilled(&[self])).emit()
+    }
+}
+
+/// Data to log when a pool lends `amount` to `receiver_id` by a flash swap.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+pub struct FlashSwapStarted<'a> {
+    pub receiver_id: &'a AccountId,
+    pub pool_id: u64,
+    pub token_id: &'a AccountId,
+    pub amount: &'a U128,
+    pub fee: &'a U128,
+}
+
+impl FlashSwapStarted<'_> {
+    pub fn emit(self) {
+        new_event(SimpleSwapEventKind::FlashSwapStarted(&[self])).emit()
+    }
+}
+
+/// Data to log when `receiver_id` pays a flash swap back, the fee first, in full or the
+/// part held when it is resolved.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+pub struct FlashSwapRepaid<'a> {
+    pub receiver_id: &'a AccountId,
+    pub pool_id: u64,
+    pub token_id: &'a AccountId,
+    pub amount: &'a U128,
+}
+
+impl FlashSwapRepaid<'_> {
+    pub fn emit(self) {
+        new_event(SimpleSwapEventKind::FlashSwapRepaid(&[self])).emit()
+    }
+}
+
+/// Data to log when tokens are given back to `account_id` instead of being used.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+pub struct Refund<'a> {
+    pub account_id: &'a AccountId,
+    pub token_id: &'a AccountId,
+    pub amount: &'a U128,
+    pub reason: &'a str,
+}
+
+impl Refund<'_> {
+    pub fn emit(self) {
+        new_event(SimpleSwapEventKind::Refund(&[self])).emit()
+    }
+}
+
+/// Data to log when a refund to `account_id` is kept in the lost and found balance
+/// because the account unregistered before it arrived.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+pub struct LostFound<'a> {
+    pub account_id: &'a AccountId,
+    pub token_id: &'a AccountId,
+    pub amount: &'a U128,
+    pub reason: &'a str,
+}
+
+impl LostFound<'_> {
+    pub fn emit(self) {
+        new_event(SimpleSwapEventKind::LostFound(&[self])).emit()
+    }
+}
+
+/// Data to log when the owner moves the lost and found balance to its internal balance.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+pub struct LostFoundClaimed<'a> {
+    pub account_id: &'a AccountId,
+    pub token_id: &'a AccountId,
+    pub amount: &'a U128,
+}
+
+impl LostFoundClaimed<'_> {
+    pub fn emit(self) {
+        new_event(SimpleSwapEventKind::LostFoundClaimed(&[self])).emit()
+    }
+}
+
+/// Data to log when the swap fee changes, fees are in basis points.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+pub struct FeeChanged<'a> {
+    pub account_id: &'a AccountId,
+    pub old_fee: u32,
+    pub new_fee: u32,
+}
+
+impl FeeChanged<'_> {
+    pub fn emit(self) {
+        new_event(SimpleSwapEventKind::FeeChanged(&[self])).emit()
+    }
+}
+
+/// Data to log when the referral share of the swap fee changes.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+pub struct ReferralFeeChanged<'a> {
+    pub account_id: &'a AccountId,
+    pub old_fee: u32,
+    pub new_fee: u32,
+}
+
+impl ReferralFeeChanged<'_> {
+    pub fn emit(self) {
+        new_event(SimpleSwapEventKind::ReferralFeeChanged(&[self])).emit()
+    }
+}
+
+/// Data to log when a referrer earns a share of the fee of the swap `swap_id`.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+pub struct ReferralFeePaid<'a> {
+    pub swap_id: &'a U64,
+    pub referral_id: &'a AccountId,
+    pub token_id: &'a AccountId,
+    pub amount: &'a U128,
+}
+
+impl ReferralFeePaid<'_> {
+    pub fn emit(self) {
+        new_event(SimpleSwapEventKind::ReferralFeePaid(&[self])).emit()
+    }
+}
+
+/// Data to log when a referrer moves its fees to the internal balances.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+pub struct ReferralFeesClaimed<'a> {
+    pub account_id: &'a AccountId,
+    pub fees: &'a HashMap<AccountId, U128>,
+}
+
+impl ReferralFeesClaimed<'_> {
+    pub fn emit(self) {
+        new_event(SimpleSwapEventKind::ReferralFeesClaimed(&[self])).emit()
+    }
+}
+
+/// Data to log when the gas config changes.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+pub struct GasConfigChanged<'a> {
+    pub account_id: &'a AccountId,
+    #[serde(flatten)]
+    pub gas_config: &'a GasConfig,
+}
+
+impl GasConfigChanged<'_> {
+    pub fn emit(self) {
+        new_event(SimpleSwapEventKind::GasConfigChanged(&[self])).emit()
+    }
+}
+
+/// Data to log when the circuit breaker limits change.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+pub struct CircuitBreakerChanged<'a> {
+    pub account_id: &'a AccountId,
+    #[serde(flatten)]
+    pub circuit_breaker: &'a CircuitBreaker,
+}
+
+impl CircuitBreakerChanged<'_> {
+    pub fn emit(self) {
+        new_event(SimpleSwapEventKind::CircuitBreakerChanged(&[self])).emit()
+    }
+}
+
+/// Data to log when the pause flags change.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+pub struct PauseChanged<'a> {
+    pub account_id: &'a AccountId,
+    #[serde(flatten)]
+    pub paused: &'a PauseFlags,
+}
+
+impl PauseChanged<'_> {
+    pub fn emit(self) {
+        new_event(SimpleSwapEventKind::PauseChanged(&[self])).emit()
+    }
+}
+
+/// Data to log when the owner proposes a new owner.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+pub struct OwnerProposed<'a> {
+    pub owner_id: &'a AccountId,
+    pub new_owner_id: &'a AccountId,
+}
+
+impl OwnerProposed<'_> {
+    pub fn emit(self) {
+        new_event(SimpleSwapEventKind::OwnerProposed(&[self])).emit()
+    }
+}
+
+/// Data to log when the proposed owner accepts the ownership.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+pub struct OwnerAccepted<'a> {
+    pub old_owner_id: &'a AccountId,
+    pub new_owner_id: &'a AccountId,
+}
+
+impl OwnerAccepted<'_> {
+    pub fn emit(self) {
+        new_event(SimpleSwapEventKind::OwnerAccepted(&[self])).emit()
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[must_use = "don't forget to `.emit()` this event"]
+struct SimpleSwapEvent<'a> {
+    standard: &'static str,
+    version: &'static str,
+    #[serde(flatten)]
+    event_kind: SimpleSwapEventKind<'a>,
+}
+
+impl SimpleSwapEvent<'_> {
+    fn emit(self) {
+        // Events cannot fail to serialize so fine to panic on error
+        let json = serde_json::to_string(&self).unwrap_or_else(|_| env::abort());
+        env::log_str(&format!("EVENT_JSON:{}", json));
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "event", content = "data")]
+#[serde(rename_all = "snake_case")]
+enum SimpleSwapEventKind<'a> {
+    Swap(&'a [Swap<'a>]),
+    Deposit(&'a [Deposit<'a>]),
+    Withdraw(&'a [Withdraw<'a>]),
+    AddLiquidity(&'a [AddLiquidity<'a>]),
+    RemoveLiquidity(&'a [RemoveLiquidity<'a>]),
+    AddPosition(&'a [AddPosition<'a>]),
+    RemovePosition(&'a [RemovePosition<'a>]),
+    ClaimPositionFees(&'a [ClaimPositionFees<'a>]),
+    LimitOrderPlaced(&'a [LimitOrderPlaced<'a>]),
+    LimitOrderCancelled(&'a [LimitOrderCancelled<'a>]),
+    LimitOrderFilled(&'a [LimitOrderFilled<'a>]),
+    FlashSwapStarted(&'a [FlashSwapStarted<'a>]),
+    FlashSwapRepaid(&'a [FlashSwapRepaid<'a>]),
+    ReferralFeePaid(&'a [ReferralFeePaid<'a>]),
+    ReferralFeesClaimed(&'a [ReferralFeesClaimed<'a>]),
+    Refund(&'a [Refund<'a>]),
+    LostFound(&'a [LostFound<'a>]),
+    LostFoundClaimed(&'a [LostFoundClaimed<'a>]),
+    FeeChanged(&'a [FeeChanged<'a>]),
+    ReferralFeeChanged(&'a [ReferralFeeChanged<'a>]),
+    GasConfigChanged(&'a [GasConfigChanged<'a>]),
+    CircuitBreakerChanged(&'a [CircuitBreakerChanged<'a>]),
+    PauseChanged(&'a [PauseChanged<'a>]),
+    OwnerProposed(&'a [OwnerProposed<'a>]),
+    OwnerAccepted(&'a [OwnerAccepted<'a>]),
+}
+
+fn new_event(event_kind: SimpleSwapEventKind) -> SimpleSwapEvent {
+    SimpleSwapEvent {
+        standard: EVENT_STANDARD,
+        version: EVENT_STANDARD_VERSION,
+        event_kind,
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{self, accounts};
+
+    use super::*;
+
+    #[test]
+    fn swap() {
+        Swap {
+            swap_id: &U64(0),
+            pool_id: 2,
+            account_id: &accounts(1),
+            token_in: &accounts(3),
+            amount_in: &U128(100),
+            token_out: &accounts(4),
+            amount_out: &U128(90),
+            fee: &U128(0),
+        }
+        .emit();
+        assert_eq!(
+            test_utils::get_logs()[0],
+            r#"EVENT_JSON:{"standard":"simple-swap","version":"1.0.0","event":"swap","data":[{"swap_id":"0","pool_id":2,"account_id":"bob","token_in":"danny","amount_in":"100","token_out":"eugene","amount_out":"90","fee":"0"}]}"#
+        );
+    }
+
+    #[test]
+    fn pause_changed() {
+        PauseChanged {
+            account_id: &accounts(0),
+            paused: &PauseFlags {
+                swap: true,
+                ..Default::default()
+            },
+        }
+        .emit();
+        assert_eq!(
+            test_utils::get_logs()[0],
+            r#"EVENT_JSON:{"standard":"simple-swap","version":"1.0.0","event":"pause_changed","data":[{"account_id":"alice","swap":true,"add_liquidity":false,"withdraw":false}]}"#
+        );
+    }
+
+    #[test]
+    fn owner_proposed() {
+        OwnerProposed {
+            owner_id: &accounts(0),
+            new_owner_id: &accounts(1),
+        }
+        .emit();
+        assert_eq!(
+            test_utils::get_logs()[0],
+            r#"EVENT_JSON:{"standard":"simple-swap","version":"1.0.0","event":"owner_proposed","data":[{"owner_id":"alice","new_owner_id":"bob"}]}"#
+        );
+    }
+}
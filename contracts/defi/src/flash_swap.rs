@@ -0,0 +1,197 @@
+use near_sdk::PromiseOrValue;
+
+use crate::errors::ensure;
+use crate::events::{FlashSwapRepaid, FlashSwapStarted};
+use crate::simple_pool::add_reserve;
+use crate::*;
+
+/// interface of the contracts receiving flash swaps
+#[ext_contract(ext_flash_swap_receiver)]
+pub trait FlashSwapReceiver {
+    /// `amount` of `token_id` of the pool `pool_id` has been credited to the internal balance
+    /// of the receiver, it can't be swapped or withdrawn until the loan is paid back.
+    /// `amount + fee` of `token_id` must be in the internal balance when the returned promise
+    /// resolves, a short balance is clawed back and the rest stays due
+    fn on_flash_swap(
+        &mut self,
+        pool_id: u64,
+        token_id: AccountId,
+        amount: U128,
+        fee: U128,
+        msg: String,
+    ) -> PromiseOrValue<()>;
+}
+
+/// tokens lent by a pool, waiting to be paid back with the fee
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct FlashSwap {
+    pub pool_id: u64,
+    pub token_id: AccountId,
+    pub amount: U128,
+    pub fee: U128,
+}
+
+#[near_bindgen]
+impl DeFi {
+    /// lend `amount` of `token_out` of the pool `pool_id` to the caller's internal balance
+    /// and call its `on_flash_swap` with `msg`. the pool can't be swapped with or have its
+    /// liquidity changed until the loan and the swap fee are paid back from that balance,
+    /// the caller can't swap or withdraw until then. return whether the resolve step paid
+    /// the loan back
+    pub fn flash_swap(
+        &mut self,
+        pool_id: u64,
+        token_out: AccountId,
+        amount: U128,
+        msg: String,
+    ) -> Promise {
+        ensure(!self.paused.swap, DeFiError::SwapPaused);
+        ensure(amount.0 > 0, DeFiError::ZeroAmount);
+        self.assert_enough_gas("flash_swap");
+        let receiver_id = env::predecessor_account_id();
+        self.assert_no_flash_swap(&receiver_id);
+        self.assert_no_pool_flash_swap(pool_id);
+        let mut pool = self.internal_get_pool(pool_id);
+        let token_index = pool
+            .token_index(&token_out)
+            .unwrap_or_else(|| DeFiError::UnknownToken.panic());
+        let fee = U128(pool.fee_of(amount.0));
+        self.internal_update_oracle(pool_id, &pool);
+        let reserve = &mut pool.flash_amounts_mut()[token_index];
+        ensure(amount.0 < *reserve, DeFiError::NotEnoughLiquidity);
+        ensure(
+            self.check_loan_move(pool_id, *reserve, amount.0).is_ok(),
+            DeFiError::PriceMoveTooLarge,
+        );
+        *reserve -= amount.0;
+        self.pools.replace(pool_id, &pool);
+
+        let prev_storage = env::storage_usage();
+        self.flash_swaps.insert(
+            &receiver_id,
+            &FlashSwap {
+                pool_id,
+                token_id: token_out.clone(),
+                amount,
+                fee,
+            },
+        );
+        self.flash_swap_pools.insert(&pool_id, &receiver_id);
+        self.internal_deposit(&receiver_id, &token_out, amount.0);
+        self.internal_update_storage(&receiver_id, prev_storage);
+        FlashSwapStarted {
+            receiver_id: &receiver_id,
+            pool_id,
+            token_id: &token_out,
+            amount: &amount,
+            fee: &fee,
+        }
+        .emit();
+        ext_flash_swap_receiver::ext(receiver_id.clone())
+            .with_static_gas(self.gas_config.flash_swap_receiver)
+            .on_flash_swap(pool_id, token_out, amount, fee, msg)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(self.gas_config.callback)
+                    .resolve_flash_swap(receiver_id),
+            )
+    }
+
+    /// pay the flash swap of `receiver_id` back, whatever `on_flash_swap` returned. a short
+    /// balance is clawed back and the rest stays due, the pool stays locked so its LPs can't
+    /// withdraw the reserves short of the loan
+    #[private]
+    pub fn resolve_flash_swap(&mut self, receiver_id: AccountId) -> bool {
+        self.internal_repay_flash_swap(&receiver_id)
+    }
+
+    /// pay what is still due of the caller's flash swap from its internal balance, e.g. when
+    /// the balance was short in the resolve step, and unlock the pool once it is paid back
+    /// in full. return whether it is paid back in full
+    pub fn repay_flash_swap(&mut self) -> bool {
+        let account_id = env::predecessor_account_id();
+        ensure(
+            self.flash_swaps.contains_key(&account_id),
+            DeFiError::NoFlashSwap,
+        );
+        self.internal_repay_flash_swap(&account_id)
+    }
+
+    /// what `account_id` still has to pay back of its flash swap
+    pub fn get_flash_swap(&self, account_id: AccountId) -> Option<FlashSwap> {
+        self.flash_swaps.get(&account_id)
+    }
+}
+
+impl DeFi {
+    pub(crate) fn assert_no_flash_swap(&self, account_id: &AccountId) {
+        ensure(
+            !self.flash_swaps.contains_key(account_id),
+            DeFiError::FlashSwapInProgress,
+        );
+    }
+
+    /// whether the pool `pool_id` lent its reserves to a flash swap which isn't resolved yet
+    pub(crate) fn pool_has_flash_swap(&self, pool_id: u64) -> bool {
+        self.flash_swap_pools.contains_key(&pool_id)
+    }
+
+    pub(crate) fn assert_no_pool_flash_swap(&self, pool_id: u64) {
+        ensure(
+            !self.pool_has_flash_swap(pool_id),
+            DeFiError::FlashSwapInProgress,
+        );
+    }
+
+    /// move the loan and its fee from the internal balance of `receiver_id` back to the
+    /// pool and unlock it. a short balance is taken whole, the fee first, and the rest stays
+    /// due with the pool locked
+    fn internal_repay_flash_swap(&mut self, receiver_id: &AccountId) -> bool {
+        let mut flash_swap = match self.flash_swaps.get(receiver_id) {
+            Some(flash_swap) => flash_swap,
+            None => return false,
+        };
+        let due = flash_swap.amount.0 + flash_swap.fee.0;
+        let balance = self
+            .accounts
+            .get(receiver_id)
+            .map_or(0, |account| account.get_balance(&flash_swap.token_id));
+        let paid = balance.min(due);
+        let prev_storage = env::storage_usage();
+        if paid > 0 {
+            self.internal_withdraw(receiver_id, &flash_swap.token_id, paid);
+        }
+        let repaid = paid == due;
+        if repaid {
+            self.flash_swaps.remove(receiver_id);
+            self.flash_swap_pools.remove(&flash_swap.pool_id);
+        } else {
+            let fee_paid = paid.min(flash_swap.fee.0);
+            flash_swap.fee = U128(flash_swap.fee.0 - fee_paid);
+            flash_swap.amount = U128(flash_swap.amount.0 - (paid - fee_paid));
+            self.flash_swaps.insert(receiver_id, &flash_swap);
+            log!("flash swap not paid back, {} is still due", due - paid);
+        }
+        self.internal_track_storage(receiver_id, prev_storage);
+        if paid == 0 {
+            return false;
+        }
+
+        let mut pool = self.internal_get_pool(flash_swap.pool_id);
+        self.internal_update_oracle(flash_swap.pool_id, &pool);
+        let token_index = pool
+            .token_index(&flash_swap.token_id)
+            .unwrap_or_else(|| DeFiError::UnknownToken.panic());
+        let reserve = &mut pool.flash_amounts_mut()[token_index];
+        *reserve = add_reserve(*reserve, paid);
+        self.pools.replace(flash_swap.pool_id, &pool);
+        FlashSwapRepaid {
+            receiver_id,
+            pool_id: flash_swap.pool_id,
+            token_id: &flash_swap.token_id,
+            amount: &U128(paid),
+        }
+        .emit();
+        repaid
+    }
+}
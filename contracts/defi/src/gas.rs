@@ -0,0 +1,106 @@
+use crate::events::GasConfigChanged;
+use crate::*;
+
+/// gas of each step of the methods which call token contracts
+#[derive(
+    BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug,
+)]
+pub struct GasConfig {
+    /// kept for the entry method itself
+    pub entry: Gas,
+    /// `ft_metadata` of a token contract
+    pub ft_metadata: Gas,
+    /// `ft_transfer` of a token contract, also `near_deposit` and `near_withdraw` of wNEAR
+    pub ft_transfer: Gas,
+    /// each callback to this contract
+    pub callback: Gas,
+    /// `on_flash_swap` of a flash swap receiver
+    pub flash_swap_receiver: Gas,
+}
+
+impl Default for GasConfig {
+    fn default() -> Self {
+        Self {
+            entry: Gas(10 * TGAS),
+            ft_metadata: Gas(5 * TGAS),
+            ft_transfer: Gas(5 * TGAS),
+            callback: Gas(5 * TGAS),
+            flash_swap_receiver: Gas(50 * TGAS),
+        }
+    }
+}
+
+impl GasConfig {
+    /// minimum prepaid gas of the entry method `method_name`
+    pub fn required(&self, method_name: &str) -> Gas {
+        match method_name {
+            "set_token_info" => self.entry + self.ft_metadata + self.callback,
+            "withdraw" => self.entry + self.ft_transfer + self.callback,
+            "flash_swap" => self.entry + self.flash_swap_receiver + self.callback,
+            // the liquidity change runs in a second callback with `entry` gas
+            "add_liquidity_near" => self.entry + self.ft_transfer + self.callback + self.entry,
+            // the swap runs in a second callback, which unwraps the NEAR again if it is rejected
+            "swap_near" => {
+                self.entry + self.ft_transfer + self.callback + self.required("withdraw_near")
+            }
+            "withdraw_near" => self.entry + self.ft_transfer + self.callback,
+            _ => DeFiError::UnknownMethod.panic(),
+        }
+    }
+}
+
+pub trait GasManagement {
+    /// replace the gas config, only the owner can call it
+    fn set_gas_config(&mut self, gas_config: GasConfig);
+
+    fn get_gas_config(&self) -> GasConfig;
+
+    /// minimum prepaid gas of `set_token_info`, `withdraw`, `flash_swap`, `swap_near`,
+    /// `add_liquidity_near` or `withdraw_near`
+    fn get_required_gas(&self, method_name: String) -> Gas;
+}
+
+#[near_bindgen]
+impl GasManagement for DeFi {
+    fn set_gas_config(&mut self, gas_config: GasConfig) {
+        self.assert_role(Role::Owner);
+        ensure(
+            [
+                gas_config.entry,
+                gas_config.ft_metadata,
+                gas_config.ft_transfer,
+                gas_config.callback,
+                gas_config.flash_swap_receiver,
+            ]
+            .iter()
+            .all(|gas| gas.0 > 0),
+            DeFiError::InvalidGasConfig,
+        );
+        self.gas_config = gas_config;
+        GasConfigChanged {
+            account_id: &env::predecessor_account_id(),
+            gas_config: &self.gas_config,
+        }
+        .emit();
+    }
+
+    fn get_gas_config(&self) -> GasConfig {
+        self.gas_config
+    }
+
+    fn get_required_gas(&self, method_name: String) -> Gas {
+        self.gas_config.required(&method_name)
+    }
+}
+
+impl DeFi {
+    /// panic early if the prepaid gas can't cover `method_name`,
+    /// instead of failing in the middle of the promise chain
+    pub(crate) fn assert_enough_gas(&self, method_name: &str) {
+        let required = self.gas_config.required(method_name);
+        ensure(
+            env::prepaid_gas() >= required,
+            DeFiError::NotEnoughGas(required.0 / TGAS),
+        );
+    }
+}
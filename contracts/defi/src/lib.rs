@@ -1,27 +1,102 @@
 /*!
 Some hypothetical DeFi contract that will do smart things with the transferred tokens
 */
-use near_contract_standards::fungible_token::core::ext_ft_core;
 use near_contract_standards::fungible_token::metadata::ext_ft_metadata;
 use near_contract_standards::fungible_token::metadata::FungibleTokenMetadata;
 use near_contract_standards::storage_management::StorageBalance;
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::LookupMap;
-use near_sdk::json_types::U128;
+use near_sdk::collections::{LookupMap, UnorderedMap, UnorderedSet, Vector};
+use near_sdk::json_types::{U128, U64};
 use near_sdk::{
     env, ext_contract, log, near_bindgen, serde, AccountId, Balance, Gas, PanicOnDefault,
-    PromiseOrValue,
+    StorageUsage,
 };
-use near_sdk::{BorshStorageKey, Promise, PromiseError};
+use near_sdk::{BorshStorageKey, FunctionError, Promise, PromiseError};
 use serde::{Deserialize, Serialize};
 
+pub use crate::access_control::{AccessControl, Role};
+pub use crate::account_deposit::Account;
+pub use crate::batch_swap::SwapRequest;
+use crate::circuit_breaker::PriceWindow;
+pub use crate::circuit_breaker::{CircuitBreaker, CircuitBreakerManagement};
+pub use crate::concentrated_pool::{ConcentratedPool, PositionInfo};
+use crate::errors::ensure;
+pub use crate::errors::DeFiError;
+use crate::events::{AddLiquidity, FeeChanged, RemoveLiquidity, Swap};
+pub use crate::flash_swap::{FlashSwap, FlashSwapReceiver};
+pub use crate::gas::{GasConfig, GasManagement};
+pub use crate::limit_order::LimitOrder;
+use crate::limit_order::LimitOrderBook;
+pub use crate::native_near::WrappedNear;
+use crate::oracle::TwapOracle;
+pub use crate::oracle::PRICE_PRECISION;
+pub use crate::owner::Ownable;
+pub use crate::pause::{Pausable, PauseFlags};
+pub use crate::pool::Pool;
+use crate::referral::Referral;
+pub use crate::referral::ReferralStats;
+pub use crate::simple_pool::SimplePool;
+pub use crate::stable_swap::StableSwapPool;
+pub use crate::upgrade::{VersionedDeFi, STATE_VERSION};
+use crate::utils::{paginate, U256};
+pub use crate::weighted_pool::WeightedPool;
+pub use crate::whitelist::TokenWhitelist;
+
+mod access_control;
+mod account_deposit;
+mod batch_swap;
+mod circuit_breaker;
+mod concentrated_pool;
+pub mod errors;
+pub mod events;
+mod flash_swap;
+mod gas;
+mod limit_order;
+mod native_near;
+mod oracle;
+mod owner;
+mod pause;
+mod pool;
+mod referral;
+mod simple_pool;
+mod stable_swap;
+mod storage_impl;
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod test_utils;
+mod token_receiver;
+mod upgrade;
+mod utils;
+mod weighted_pool;
+mod whitelist;
+
 pub const TGAS: u64 = 1_000_000_000_000;
-const INITIAL_BALANCE: Balance = 250_000_000_000_000_000_000_000;
+/// swap fee is charged in basis points of the input amount
+pub const FEE_DIVISOR: u32 = 10_000;
+const DEFAULT_TOTAL_FEE: u32 = 30;
+const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 #[derive(BorshStorageKey, BorshSerialize)]
 pub enum StoreKey {
     Token,
     Decimals,
+    Roles,
+    Accounts,
+    Shares { pool_id: u64 },
+    Pools,
+    Whitelist,
+    Tokens,
+    Tickers,
+    Observations,
+    Ticks { pool_id: u64 },
+    Positions { pool_id: u64 },
+    AccountPositions { pool_id: u64 },
+    LimitOrders,
+    AccountLimitOrders,
+    FlashSwaps,
+    PriceWindows,
+    Referrals,
+    FlashSwapPools,
+    LostFound,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -38,19 +113,149 @@ pub struct TokenInfo {
     decimals: u8,
 }
 
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SwapStatus {
+    /// the target token is credited to the user
+    Success,
+    /// refused before any token is moved, e.g. swap is paused, the deadline has passed or
+    /// the pool lent its reserves to a flash swap
+    Rejected,
+}
+
+/// optional arguments of `swap`
+#[derive(Deserialize, Serialize, Clone, Default, Debug)]
+pub struct SwapOptions {
+    /// block timestamp in nanoseconds after which the swap is rejected
+    pub deadline: Option<U64>,
+    /// unwrap a wNEAR output and send it to the caller as NEAR
+    #[serde(default)]
+    pub unwrap_near: bool,
+    /// registered account earning the referral share of the fee, concentrated liquidity
+    /// pools pay no referral fee
+    pub referral_id: Option<AccountId>,
+}
+
+/// outcome of `swap_token`, token ids are the token contract addresses
+#[derive(Deserialize, Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct SwapResult {
+    pub swap_id: U64,
+    pub token_in: AccountId,
+    pub amount_in: U128,
+    pub token_out: AccountId,
+    pub amount_out: U128,
+    /// fee charged, in `token_in`. the pool keeps it but for the referral share
+    pub fee: U128,
+    pub status: SwapStatus,
+}
+
+#[derive(Serialize)]
+pub struct PoolInfo {
+    owner_id: AccountId,
+    token_a: Option<TokenInfo>,
+    token_b: Option<TokenInfo>,
+    reserve_a: U128,
+    reserve_b: U128,
+    total_fee: u32,
+    shares_total_supply: U128,
+    paused: PauseFlags,
+    circuit_breaker: CircuitBreaker,
+    version: String,
+}
+
+/// summary of a pool for listings
+#[derive(Serialize)]
+pub struct PoolSummary {
+    /// `SIMPLE_POOL`, `STABLE_SWAP`, `WEIGHTED_POOL` or `CONCENTRATED_LIQUIDITY`
+    pool_kind: String,
+    token_account_ids: Vec<AccountId>,
+    amounts: Vec<U128>,
+    total_fee: u32,
+    /// LP shares issued, the liquidity of every position of a concentrated liquidity pool
+    shares_total_supply: U128,
+    /// current amp factor of a StableSwap pool
+    amp_factor: Option<U128>,
+    /// token weights of a weighted pool in basis points
+    weights: Option<Vec<u32>>,
+    /// tick of the current price of a concentrated liquidity pool
+    current_tick: Option<i32>,
+    /// liquidity in range of a concentrated liquidity pool
+    liquidity: Option<U128>,
+}
+
+impl From<Pool> for PoolSummary {
+    fn from(pool: Pool) -> Self {
+        Self {
+            pool_kind: pool.kind().to_string(),
+            token_account_ids: pool.token_account_ids().to_vec(),
+            amounts: pool.amounts().iter().map(|a| U128(*a)).collect(),
+            total_fee: pool.total_fee(),
+            shares_total_supply: U128(pool.shares_total_supply()),
+            amp_factor: match &pool {
+                Pool::StableSwapPool(pool) => Some(U128(pool.amp_factor())),
+                _ => None,
+            },
+            weights: match &pool {
+                Pool::WeightedPool(pool) => Some(pool.weights.clone()),
+                _ => None,
+            },
+            current_tick: match &pool {
+                Pool::ConcentratedPool(pool) => Some(pool.current_tick),
+                _ => None,
+            },
+            liquidity: match &pool {
+                Pool::ConcentratedPool(pool) => Some(U128(pool.liquidity)),
+                _ => None,
+            },
+        }
+    }
+}
+
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
 pub struct DeFi {
     // owner address
     owner_id: AccountId,
-    address_a: AccountId,
-    address_b: AccountId,
-    ratio: U128,
+    // proposed owner, waiting for `accept_ownership`
+    pending_owner_id: Option<AccountId>,
     // (symbol, token_info) map
-    tokens: LookupMap<String, TokenInfo>,
+    tokens: UnorderedMap<String, TokenInfo>,
     // (token_address, ticker) map
-    tickers: LookupMap<AccountId, String>,
-    pending: bool,
+    tickers: UnorderedMap<AccountId, String>,
+    // pools by id, pool 0 is the TokenA / TokenB pool created by `new`
+    pools: Vector<Pool>,
+    // tokens which can be deposited and pooled
+    whitelisted_tokens: UnorderedSet<AccountId>,
+    paused: PauseFlags,
+    // id of the next swap
+    next_swap_id: u64,
+    // (account, granted roles) map, the owner holds every role implicitly
+    roles: LookupMap<AccountId, Vec<Role>>,
+    // (account, internal balances) map
+    accounts: LookupMap<AccountId, Account>,
+    // storage of an empty account, the minimum storage deposit
+    account_storage_usage: StorageUsage,
+    // gas of the calls to token contracts
+    gas_config: GasConfig,
+    // price accumulators of the TokenA / TokenB pool
+    oracle: TwapOracle,
+    // open limit orders and their escrow
+    limit_orders: LimitOrderBook,
+    // (receiver, flash swap waiting for the repayment) map
+    flash_swaps: LookupMap<AccountId, FlashSwap>,
+    // wNEAR contract wrapping the NEAR attached to `swap_near` and `add_liquidity_near`
+    wnear_id: Option<AccountId>,
+    // limits of the price moves caused by swaps
+    circuit_breaker: CircuitBreaker,
+    // (pool, price moves in the current window) map
+    price_windows: LookupMap<u64, PriceWindow>,
+    // share of the swap fee paid to referrers, in basis points of the fee
+    referral_fee: u32,
+    // (referrer, fees earned) map
+    referrals: LookupMap<AccountId, Referral>,
+    // (pool, receiver) map of the flash swaps waiting for the repayment
+    flash_swap_pools: LookupMap<u64, AccountId>,
+    // (token, balance) map of the refunds to accounts which unregistered meanwhile
+    lost_found: LookupMap<AccountId, Balance>,
 }
 
 // Defining cross-contract interface. This allows to create a new promise.
@@ -63,53 +268,78 @@ pub trait External {
     ) -> StorageBalance;
 }
 
-fn create_subaccount(prefix: &str) -> Promise {
-    let subaccount_id =
-        AccountId::new_unchecked(format!("{}.{}", prefix, env::current_account_id()));
-    Promise::new(subaccount_id)
-        .create_account()
-        .add_full_access_key(env::signer_account_pk())
-        .transfer(INITIAL_BALANCE)
+/// swaps and liquidity changes run in a single receipt, so checking the deadline
+/// at the entry is also the last check before the balances change
+fn deadline_passed(deadline: Option<U64>) -> bool {
+    deadline.is_some_and(|deadline| env::block_timestamp() > deadline.0)
+}
+
+fn assert_deadline(deadline: Option<U64>) {
+    ensure(!deadline_passed(deadline), DeFiError::DeadlinePassed);
 }
 
 #[near_bindgen]
 impl DeFi {
     #[init]
     pub fn new(owner_id: AccountId, token_a: TokenConfig, token_b: TokenConfig) -> Self {
-        assert!(!env::state_exists(), "Already initialized");
-        let tokens = LookupMap::new(StoreKey::Token);
-        let mut tickers = LookupMap::new(StoreKey::Decimals);
+        ensure(!env::state_exists(), DeFiError::AlreadyInitialized);
+        let tokens = UnorderedMap::new(StoreKey::Tokens);
+        let mut tickers = UnorderedMap::new(StoreKey::Tickers);
         tickers.insert(&token_a.address, &token_a.ticker);
         tickers.insert(&token_b.address, &token_b.ticker);
-        create_subaccount("token_a");
-        create_subaccount("token_b");
-        let address_a = AccountId::new_unchecked(format!("token_a.{}", env::current_account_id()));
-        let address_b = AccountId::new_unchecked(format!("token_b.{}", env::current_account_id()));
-        Self {
+        let mut whitelisted_tokens = UnorderedSet::new(StoreKey::Whitelist);
+        whitelisted_tokens.insert(&token_a.address);
+        whitelisted_tokens.insert(&token_b.address);
+        let mut pools = Vector::new(StoreKey::Pools);
+        pools.push(&Pool::SimplePool(SimplePool::new(
+            0,
+            vec![token_a.address, token_b.address],
+            DEFAULT_TOTAL_FEE,
+        )));
+        upgrade::write_state_version();
+        let mut this = Self {
             owner_id,
+            pending_owner_id: None,
             tokens,
             tickers,
-            address_a,
-            address_b,
-            ratio: U128(0),
-            pending: false,
-        }
+            pools,
+            whitelisted_tokens,
+            paused: PauseFlags::default(),
+            next_swap_id: 0,
+            roles: LookupMap::new(StoreKey::Roles),
+            accounts: LookupMap::new(StoreKey::Accounts),
+            account_storage_usage: 0,
+            gas_config: GasConfig::default(),
+            oracle: TwapOracle::new(),
+            limit_orders: LimitOrderBook::new(),
+            flash_swaps: LookupMap::new(StoreKey::FlashSwaps),
+            wnear_id: None,
+            circuit_breaker: CircuitBreaker::default(),
+            price_windows: LookupMap::new(StoreKey::PriceWindows),
+            referral_fee: 0,
+            referrals: LookupMap::new(StoreKey::Referrals),
+            flash_swap_pools: LookupMap::new(StoreKey::FlashSwapPools),
+            lost_found: LookupMap::new(StoreKey::LostFound),
+        };
+        this.measure_account_storage_usage();
+        this
     }
 
-    #[private]
     pub fn set_token_info(&mut self, token_address: AccountId) {
-        let gas = Gas(5 * TGAS);
+        self.assert_role(Role::Owner);
+        self.assert_enough_gas("set_token_info");
         // get the token meta data and store the token
         let p1: Promise = ext_ft_metadata::ext(token_address.clone())
             // .with_attached_deposit(1)
-            .with_static_gas(gas)
+            .with_static_gas(self.gas_config.ft_metadata)
             .ft_metadata();
         let p2 = Self::ext(env::current_account_id())
-            .with_static_gas(gas)
+            .with_static_gas(self.gas_config.callback)
             .set_token_info_callback(token_address);
         p1.then(p2);
     }
 
+    #[private]
     pub fn set_token_info_callback(
         &mut self,
         token_address: AccountId,
@@ -135,241 +365,1217 @@ impl DeFi {
         self.tokens.get(&symbol)
     }
 
+    /// tokens stored by `set_token_info`
+    pub fn get_tokens(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<TokenInfo> {
+        paginate(self.tokens.values_as_vector(), from_index, limit)
+    }
+
+    /// pools in the order of their ids
+    pub fn get_pools(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<PoolSummary> {
+        paginate(&self.pools, from_index, limit)
+            .into_iter()
+            .map(PoolSummary::from)
+            .collect()
+    }
+
+    pub fn get_pool(&self, pool_id: u64) -> PoolSummary {
+        self.internal_get_pool(pool_id).into()
+    }
+
+    /// get the summary of the TokenA / TokenB pool in one call
+    pub fn get_pool_info(&self) -> PoolInfo {
+        let pool = self.internal_get_pool(0);
+        PoolInfo {
+            owner_id: self.owner_id.clone(),
+            token_a: self.tokens.get(&"TokenA".to_string()),
+            token_b: self.tokens.get(&"TokenB".to_string()),
+            reserve_a: U128(pool.amounts()[0]),
+            reserve_b: U128(pool.amounts()[1]),
+            total_fee: pool.total_fee(),
+            shares_total_supply: U128(pool.shares_total_supply()),
+            paused: self.paused,
+            circuit_breaker: self.circuit_breaker,
+            version: VERSION.to_string(),
+        }
+    }
+
+    /// set the swap fee of the TokenA / TokenB pool, in basis points
+    pub fn set_fee(&mut self, total_fee: u32) {
+        self.assert_role(Role::FeeManager);
+        ensure(total_fee < FEE_DIVISOR, DeFiError::FeeTooLarge);
+        let mut pool = self.internal_get_pool(0);
+        FeeChanged {
+            account_id: &env::predecessor_account_id(),
+            old_fee: pool.total_fee(),
+            new_fee: total_fee,
+        }
+        .emit();
+        pool.set_total_fee(total_fee);
+        self.pools.replace(0, &pool);
+    }
+
+    /// create a constant product pool of two whitelisted tokens, the attached deposit
+    /// pays for its storage and the rest is refunded. return the id of the pool
+    #[payable]
+    pub fn add_simple_pool(&mut self, token_ids: Vec<AccountId>, total_fee: u32) -> u64 {
+        ensure(
+            token_ids.len() == 2 && token_ids[0] != token_ids[1],
+            DeFiError::InvalidPoolTokens,
+        );
+        for token_id in token_ids.iter() {
+            self.assert_whitelisted(token_id);
+        }
+        let pool_id = self.pools.len();
+        self.internal_add_pool(Pool::SimplePool(SimplePool::new(
+            pool_id, token_ids, total_fee,
+        )))
+    }
+
+    /// add `pool` paying its storage with the attached deposit, the rest is refunded
+    fn internal_add_pool(&mut self, pool: Pool) -> u64 {
+        let prev_storage = env::storage_usage();
+        let pool_id = self.pools.len();
+        self.pools.push(&pool);
+        let storage_cost =
+            Balance::from(env::storage_usage() - prev_storage) * env::storage_byte_cost();
+        let refund = env::attached_deposit()
+            .checked_sub(storage_cost)
+            .unwrap_or_else(|| DeFiError::NotEnoughStorageDeposit.panic());
+        if refund > 0 {
+            Promise::new(env::predecessor_account_id()).transfer(refund);
+        }
+        pool_id
+    }
+
     #[inline]
     fn get_contract_address(&self, symbol: &String) -> AccountId {
-        let token_info = self.tokens.get(symbol).unwrap();
+        let token_info = self
+            .tokens
+            .get(symbol)
+            .unwrap_or_else(|| DeFiError::TokenInfoNotFound.panic());
         token_info.contract_address
     }
 
-    #[inline]
-    fn get_token_address(&self, symbol: &str) -> AccountId {
-        match symbol {
-            "TokenA" => self.address_a.clone(),
-            "TokenB" => self.address_b.clone(),
-            _ => unreachable!("only support TokenA and TokenB"),
+    /// swap `amount` of `symbol` in the caller's internal balance for the other token,
+    /// rejected if the block timestamp is past `deadline` (nanoseconds). a registered
+    /// `referral_id` earns the referral share of the fee
+    pub fn swap_token(
+        &mut self,
+        symbol: String,
+        amount: U128,
+        deadline: Option<U64>,
+        referral_id: Option<AccountId>,
+    ) -> SwapResult {
+        let symbol_target = match symbol.as_str() {
+            "TokenA" => "TokenB".to_string(),
+            "TokenB" => "TokenA".to_string(),
+            _ => DeFiError::UnknownSymbol.panic(),
+        };
+        let token_in = self.get_contract_address(&symbol);
+        let token_out = self.get_contract_address(&symbol_target);
+        let account_id = env::predecessor_account_id();
+        self.assert_referral(&account_id, referral_id.as_ref());
+        let options = SwapOptions {
+            deadline,
+            unwrap_near: false,
+            referral_id,
+        };
+        self.internal_swap(&account_id, 0, token_in, amount, token_out, &options)
+    }
+
+    /// swap `amount_in` of `token_in` in the caller's internal balance for `token_out`
+    /// in the pool `pool_id`, with the `deadline`, `unwrap_near` and `referral_id` of
+    /// `options`
+    pub fn swap(
+        &mut self,
+        pool_id: u64,
+        token_in: AccountId,
+        amount_in: U128,
+        token_out: AccountId,
+        options: Option<SwapOptions>,
+    ) -> SwapResult {
+        let options = options.unwrap_or_default();
+        if options.unwrap_near {
+            self.assert_enough_gas("withdraw_near");
+        }
+        let account_id = env::predecessor_account_id();
+        self.assert_referral(&account_id, options.referral_id.as_ref());
+        let result = self.internal_swap(
+            &account_id,
+            pool_id,
+            token_in,
+            amount_in,
+            token_out,
+            &options,
+        );
+        if options.unwrap_near
+            && result.status == SwapStatus::Success
+            && self.wnear_id.as_ref() == Some(&result.token_out)
+        {
+            self.internal_withdraw_near(&account_id, result.amount_out);
         }
+        result
     }
 
-    /// get the how many tokens of owner_id
-    /// symbol: TokenA / TokenB
-    /// return:
-    #[private]
-    pub fn get_swap_token(&mut self, symbol: String) -> PromiseOrValue<Balance> {
-        let address = match symbol.as_str() {
-            "TokenA" => self.address_a.clone(),
-            _ => self.address_b.clone(),
+    /// result of a new swap charged `fee`, rejected until `internal_swap` fills it in
+    pub(crate) fn internal_new_swap_result(
+        &mut self,
+        token_in: AccountId,
+        amount: U128,
+        token_out: AccountId,
+        fee: Balance,
+    ) -> SwapResult {
+        let result = SwapResult {
+            swap_id: U64(self.next_swap_id),
+            token_in,
+            amount_in: amount,
+            token_out,
+            amount_out: U128(0),
+            fee: U128(fee),
+            status: SwapStatus::Rejected,
         };
-        let gas = Gas(5 * TGAS);
-        let token_address = self.get_contract_address(&symbol);
-        let p = ext_ft_core::ext(token_address)
-            // .with_attached_deposit(1)
-            .with_static_gas(gas)
-            .ft_balance_of(address);
-        p.into()
+        self.next_swap_id += 1;
+        result
     }
 
-    /// store the token balance
-    pub fn update_pool_token_callback(
+    pub(crate) fn internal_swap(
         &mut self,
-        #[callback_result] call_result: Result<Balance, PromiseError>,
-    ) -> Option<Balance> {
-        match call_result {
-            Err(e) => {
-                log!("get token failed: {:?}", e);
-                None
+        account_id: &AccountId,
+        pool_id: u64,
+        token_in: AccountId,
+        amount: U128,
+        token_out: AccountId,
+        options: &SwapOptions,
+    ) -> SwapResult {
+        ensure(token_in != token_out, DeFiError::SameToken);
+        // the borrowed tokens stay in the internal balance until they are paid back
+        self.assert_no_flash_swap(account_id);
+        let mut pool = self.internal_get_pool(pool_id);
+        let fee = pool.fee_of(amount.0);
+        let mut result = self.internal_new_swap_result(token_in, amount, token_out, fee);
+        if self.paused.swap {
+            log!("swap is paused");
+            return result;
+        }
+        if deadline_passed(options.deadline) {
+            log!("deadline has passed");
+            return result;
+        }
+        if self.pool_has_flash_swap(pool_id) {
+            log!("flash swap in progress");
+            return result;
+        }
+
+        let moved = match self.check_price_move(
+            pool_id,
+            &pool,
+            &result.token_in,
+            amount.0,
+            &result.token_out,
+        ) {
+            Ok(moved) => moved,
+            Err(reason) => {
+                log!("{}", reason);
+                return result;
             }
-            Ok(balance) => Some(balance),
+        };
+
+        // the oracle observations are bounded contract state like the circuit breaker
+        // windows, the caller only pays for its own balances
+        self.internal_update_oracle(pool_id, &pool);
+        let prev_storage = env::storage_usage();
+        self.internal_withdraw(account_id, &result.token_in, amount.0);
+        let amount_out = pool.swap(&result.token_in, amount.0, &result.token_out);
+        self.internal_deposit(account_id, &result.token_out, amount_out);
+        self.internal_update_storage(account_id, prev_storage);
+        result.amount_out = U128(amount_out);
+        result.status = SwapStatus::Success;
+        Swap {
+            swap_id: &result.swap_id,
+            pool_id,
+            account_id,
+            token_in: &result.token_in,
+            amount_in: &result.amount_in,
+            token_out: &result.token_out,
+            amount_out: &result.amount_out,
+            fee: &result.fee,
         }
+        .emit();
+        // the referrer's storage isn't charged to the caller
+        self.internal_pay_referral_fee(options.referral_id.as_ref(), &mut pool, &result);
+        self.pools.replace(pool_id, &pool);
+        self.record_price_move(pool_id, moved);
+        result
     }
 
-    /// 1. user deposit TokenA to self.owner_id account
-    /// 2. calculate how many TokenB balances that should return to user
-    /// 3. self.owner_id account transfer TokenB to user account
-    pub fn swap_token(&mut self, symbol: String, amount: U128) -> PromiseOrValue<bool> {
+    /// how many `token_out` would be returned for `amount_in` of `token_in` by the pool `pool_id`
+    pub fn get_pool_return(
+        &self,
+        pool_id: u64,
+        token_in: AccountId,
+        amount_in: U128,
+        token_out: AccountId,
+    ) -> U128 {
+        U128(
+            self.internal_get_pool(pool_id)
+                .get_return(&token_in, amount_in.0, &token_out),
+        )
+    }
+
+    /// how many of the other token would be returned for `amount` of `symbol`
+    pub fn get_return(&self, symbol: String, amount: U128) -> U128 {
         let symbol_target = match symbol.as_str() {
             "TokenA" => "TokenB".to_string(),
             "TokenB" => "TokenA".to_string(),
-            _ => return PromiseOrValue::Value(false),
+            _ => DeFiError::UnknownSymbol.panic(),
         };
-        if self.pending {
-            log!("pending");
-            return PromiseOrValue::Value(false);
+        U128(self.internal_get_pool(0).get_return(
+            &self.get_contract_address(&symbol),
+            amount.0,
+            &self.get_contract_address(&symbol_target),
+        ))
+    }
+
+    /// add liquidity to the pool `pool_id` from the caller's internal balances, `amounts` are
+    /// in the order of the pool tokens. a simple pool only uses the part in proportion to the
+    /// reserves. panics if the block timestamp is past `deadline` (nanoseconds)
+    pub fn add_liquidity(
+        &mut self,
+        pool_id: u64,
+        amounts: Vec<U128>,
+        deadline: Option<U64>,
+    ) -> U128 {
+        let account_id = env::predecessor_account_id();
+        self.internal_add_liquidity(&account_id, pool_id, amounts, deadline)
+    }
+
+    pub(crate) fn internal_add_liquidity(
+        &mut self,
+        account_id: &AccountId,
+        pool_id: u64,
+        amounts: Vec<U128>,
+        deadline: Option<U64>,
+    ) -> U128 {
+        ensure(!self.paused.add_liquidity, DeFiError::AddLiquidityPaused);
+        assert_deadline(deadline);
+        self.assert_no_pool_flash_swap(pool_id);
+        let prev_storage = env::storage_usage();
+        let mut amounts: Vec<Balance> = amounts.into_iter().map(|a| a.0).collect();
+        let mut pool = self.internal_get_pool(pool_id);
+        self.internal_update_oracle(pool_id, &pool);
+        let shares = pool.add_liquidity(account_id, &mut amounts);
+        self.pools.replace(pool_id, &pool);
+        let token_ids = pool.token_account_ids().to_vec();
+        for (token_id, amount) in token_ids.iter().zip(amounts.iter()) {
+            self.internal_withdraw(account_id, token_id, *amount);
         }
-        let gas = Gas(5 * TGAS);
-        let token_info = self.tokens.get(&symbol).unwrap();
-        let token_info_target = self.tokens.get(&symbol_target).unwrap();
-        let contract_address = token_info.contract_address;
-        let contract_address_target = token_info_target.contract_address;
-        let token_address = self.get_token_address(&symbol);
-        let token_address_target = self.get_token_address(&symbol_target);
-
-        self.pending = true;
-
-        // calculate how many balance should return to user
-        let promise_token_1 = ext_ft_core::ext(contract_address.clone())
-            // .with_attached_deposit(1)
-            .with_static_gas(gas)
-            .ft_balance_of(self.owner_id.clone());
-        let promise_token_2 = ext_ft_core::ext(contract_address_target.clone())
-            // .with_attached_deposit(1)
-            .with_static_gas(gas)
-            .ft_balance_of(self.owner_id.clone());
+        self.internal_set_joined_pool(account_id, pool_id, true);
+        self.internal_update_storage(account_id, prev_storage);
+        AddLiquidity {
+            pool_id,
+            account_id,
+            token_ids: &token_ids,
+            amounts: &amounts.into_iter().map(U128).collect::<Vec<_>>(),
+            shares: &U128(shares),
+        }
+        .emit();
+        U128(shares)
+    }
 
-        let promise_user_withdraw_balance = Self::ext(env::current_account_id())
-            // .with_attached_deposit(1)
-            .with_static_gas(gas)
-            .calculate_target_token(amount);
+    /// burn `shares` of the caller in the pool `pool_id`, the tokens are credited to the
+    /// internal balances. panics if the block timestamp is past `deadline` (nanoseconds)
+    pub fn remove_liquidity(
+        &mut self,
+        pool_id: u64,
+        shares: U128,
+        min_amounts: Vec<U128>,
+        deadline: Option<U64>,
+    ) -> Vec<U128> {
+        ensure(!self.paused.withdraw, DeFiError::WithdrawPaused);
+        assert_deadline(deadline);
+        self.assert_no_pool_flash_swap(pool_id);
+        let account_id = env::predecessor_account_id();
+        let prev_storage = env::storage_usage();
+        let min_amounts: Vec<Balance> = min_amounts.into_iter().map(|a| a.0).collect();
+        let mut pool = self.internal_get_pool(pool_id);
+        self.internal_update_oracle(pool_id, &pool);
+        let amounts = pool.remove_liquidity(&account_id, shares.0, &min_amounts);
+        self.pools.replace(pool_id, &pool);
+        let token_ids = pool.token_account_ids().to_vec();
+        for (token_id, amount) in token_ids.iter().zip(amounts.iter()) {
+            self.internal_deposit(&account_id, token_id, *amount);
+        }
+        let joined = pool.share_balance_of(&account_id) > 0;
+        self.internal_set_joined_pool(&account_id, pool_id, joined);
+        self.internal_update_storage(&account_id, prev_storage);
+        let amounts: Vec<U128> = amounts.into_iter().map(U128).collect();
+        RemoveLiquidity {
+            pool_id,
+            account_id: &account_id,
+            token_ids: &token_ids,
+            amounts: &amounts,
+            shares: &shares,
+        }
+        .emit();
+        amounts
+    }
 
-        let promise_withdraw_balance = promise_token_1
-            .and(promise_token_2)
-            .then(promise_user_withdraw_balance);
+    pub fn get_shares(&self, pool_id: u64, account_id: AccountId) -> U128 {
+        U128(
+            self.internal_get_pool(pool_id)
+                .share_balance_of(&account_id),
+        )
+    }
 
-        // transfer token to token_address
-        let promise_deposit: Promise = ext_ft_core::ext(contract_address.clone())
-            .with_attached_deposit(1)
-            .with_static_gas(gas)
-            .ft_transfer(token_address, amount, None);
+    /// return  BalanceA * BalanceB
+    pub fn get_token_ratio(&self) -> U128 {
+        let pool = self.internal_get_pool(0);
+        let ratio = U256::from(pool.amounts()[0]) * U256::from(pool.amounts()[1]);
+        ensure(ratio <= U256::from(u128::MAX), DeFiError::RatioTooLarge);
+        U128(ratio.as_u128())
+    }
+}
 
-        // withdraw from the owner_id
-        let promise_swap: Promise = Self::ext(env::current_account_id())
-            .with_static_gas(gas)
-            .swap_token_withdraw(contract_address_target, env::current_account_id());
+impl DeFi {
+    pub(crate) fn internal_get_pool(&self, pool_id: u64) -> Pool {
+        self.pools
+            .get(pool_id)
+            .unwrap_or_else(|| DeFiError::PoolNotFound.panic())
+    }
 
-        promise_withdraw_balance
-            .and(promise_deposit)
-            .then(promise_swap)
-            .into()
+    /// accumulate the prices before the reserves of `pool` change,
+    /// only the TokenA / TokenB pool has an oracle
+    fn internal_update_oracle(&mut self, pool_id: u64, pool: &Pool) {
+        if pool_id == 0 {
+            self.oracle.update(pool.amounts());
+        }
     }
+}
 
-    /// transfer token from `contract_address_target` to `user_account_id`
-    pub fn swap_token_withdraw(
-        &mut self,
-        contract_address_target: AccountId,
-        user_account_id: AccountId,
-        #[callback_result] withdraw_balance: Result<U128, PromiseError>,
-        #[callback_result] deposit_result: Result<(), PromiseError>,
-    ) -> bool {
-        match (withdraw_balance, deposit_result) {
-            (Err(e1), Err(e2)) => {
-                log!("calculate x error: {:?}, user deposit error: {:?}", e1, e2);
-                // TODO: return token back to user
-                self.pending = false;
-                false
-            }
-            (Err(e), Ok(_)) => {
-                log!("calculate x error: {:?}, return token to user", e);
-                self.pending = false;
-                false
-            }
-            (Ok(_), Err(e)) => {
-                log!("user deposit error: {:?}", e);
-                self.pending = false;
-                false
-            }
-            (Ok(withdraw_amount), Ok(_)) => {
-                log!("swap token");
-                // TODO: withdraw token to user account
-                // let promise_deposit = ext_ft_core::ext(token_address_target)
-                //     .with_attached_deposit(1)
-                //     .with_static_gas(gas)
-                //     .ft_transfer(user_account_id, amount, None);
-                self.pending = false;
-                true
-            }
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, PromiseOrValue, PromiseResult};
+
+    use super::*;
+    use crate::test_utils::{deposit, register, STORAGE_DEPOSIT};
+
+    fn get_context(predecessor_account_id: AccountId) -> VMContextBuilder {
+        let mut builder = VMContextBuilder::new();
+        builder
+            .current_account_id(accounts(0))
+            .signer_account_id(predecessor_account_id.clone())
+            .predecessor_account_id(predecessor_account_id);
+        builder
+    }
+
+    fn new_contract() -> DeFi {
+        DeFi::new(
+            accounts(0),
+            TokenConfig {
+                address: accounts(3),
+                ticker: "0.1".into(),
+            },
+            TokenConfig {
+                address: accounts(4),
+                ticker: "0.01".into(),
+            },
+        )
+    }
+
+    fn metadata(symbol: &str) -> FungibleTokenMetadata {
+        FungibleTokenMetadata {
+            spec: "ft-1.0.0".into(),
+            name: format!("fungible token {}", symbol),
+            symbol: symbol.into(),
+            icon: None,
+            reference: None,
+            reference_hash: None,
+            decimals: 8,
         }
     }
 
-    /// if user deposit TokenA, calculate how many TokenB that will send to user
-    pub fn calculate_target_token(
-        &self,
-        user_balance: U128,
-        #[callback_result] token: Result<U128, PromiseError>,
-        #[callback_result] token_target: Result<U128, PromiseError>,
-    ) -> U128 {
-        if let (Ok(balance), Ok(balance_target)) = (token, token_target) {
-            if user_balance >= balance {
-                env::panic_str("too much balance")
-            }
-            // x = m / n
-            let m = (Balance::from(user_balance)).checked_mul(Balance::from(balance_target));
-            let n = (Balance::from(balance)).checked_add(Balance::from(user_balance));
-            let x = match (m, n) {
-                (Some(m), Some(n)) => U128::from(m / n),
-                _ => env::panic_str("pool balance is too large"),
-            };
-            log!(
-                "balance: {:?}, balance_target: {:?}, user balance target: {:?}",
-                balance,
-                balance_target,
-                x
-            );
-            return x;
+    /// pool with 1000 TokenA and 1000 TokenB added by the owner
+    fn pool_contract(context: &mut VMContextBuilder) -> DeFi {
+        let mut contract = new_contract();
+        contract.set_token_info_callback(accounts(3), Ok(metadata("TokenA")));
+        contract.set_token_info_callback(accounts(4), Ok(metadata("TokenB")));
+        deposit(&mut contract, context, accounts(0), accounts(3), 1000);
+        deposit(&mut contract, context, accounts(0), accounts(4), 1000);
+        contract.add_liquidity(0, vec![U128(1000), U128(1000)], None);
+        contract
+    }
+
+    #[test]
+    fn test_get_pool_info() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let contract = new_contract();
+        let info = contract.get_pool_info();
+        assert_eq!(info.owner_id, accounts(0));
+        assert!(info.token_a.is_none());
+        assert_eq!(info.reserve_a.0, 0);
+        assert_eq!(info.total_fee, DEFAULT_TOTAL_FEE);
+        assert_eq!(info.paused, PauseFlags::default());
+        assert_eq!(info.version, VERSION);
+    }
+
+    #[test]
+    fn test_add_and_remove_liquidity() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = pool_contract(&mut context);
+        let info = contract.get_pool_info();
+        assert_eq!(info.reserve_a.0, 1000);
+        assert_eq!(info.reserve_b.0, 1000);
+        assert_eq!(contract.get_token_ratio().0, 1_000_000);
+        assert!(contract.get_deposits(accounts(0)).is_empty());
+
+        let shares = contract.get_shares(0, accounts(0));
+        let amounts =
+            contract.remove_liquidity(0, U128(shares.0 / 10), vec![U128(100), U128(100)], None);
+        assert_eq!(amounts, vec![U128(100), U128(100)]);
+        assert_eq!(contract.get_deposit(accounts(0), accounts(3)).0, 100);
+        assert_eq!(contract.get_pool_info().reserve_b.0, 900);
+    }
+
+    #[test]
+    fn test_swap_token() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = pool_contract(&mut context);
+        contract.set_fee(0);
+        deposit(&mut contract, &mut context, accounts(1), accounts(3), 100);
+        assert_eq!(contract.get_return("TokenA".into(), U128(100)).0, 90);
+
+        let storage_available = contract.storage_balance_of(accounts(1)).unwrap().available;
+        let result = contract.swap_token("TokenA".into(), U128(100), None, None);
+        assert_eq!(result.status, SwapStatus::Success);
+        assert!(
+            contract
+                .storage_balance_of(accounts(1))
+                .unwrap()
+                .available
+                .0
+                < storage_available.0
+        );
+        assert_eq!(result.swap_id, U64(0));
+        assert_eq!(result.amount_out.0, 90);
+        assert_eq!(contract.get_deposit(accounts(1), accounts(3)).0, 0);
+        assert_eq!(contract.get_deposit(accounts(1), accounts(4)).0, 90);
+        let info = contract.get_pool_info();
+        assert_eq!(info.reserve_a.0, 1100);
+        assert_eq!(info.reserve_b.0, 910);
+    }
+
+    #[test]
+    #[should_panic(expected = "E023: not enough deposit")]
+    fn test_swap_token_not_enough_deposit() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = pool_contract(&mut context);
+        deposit(&mut contract, &mut context, accounts(1), accounts(3), 10);
+        contract.swap_token("TokenA".into(), U128(11), None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "E066: can't swap a token for itself")]
+    fn test_self_swap() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = pool_contract(&mut context);
+        deposit(&mut contract, &mut context, accounts(1), accounts(3), 100);
+        contract.swap(0, accounts(3), U128(100), accounts(3), None);
+    }
+
+    #[test]
+    fn test_grant_and_revoke_role() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = new_contract();
+        assert_eq!(contract.get_roles(accounts(0)), vec![Role::Owner]);
+        contract.grant_role(accounts(1), Role::FeeManager);
+        assert!(contract.has_role(accounts(1), Role::FeeManager));
+        assert!(!contract.has_role(accounts(1), Role::Pauser));
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.set_fee(10);
+        assert_eq!(contract.get_pool_info().total_fee, 10);
+
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.revoke_role(accounts(1), Role::FeeManager);
+        assert!(contract.get_roles(accounts(1)).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Pauser role required")]
+    fn test_set_paused_without_role() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = new_contract();
+        contract.grant_role(accounts(1), Role::FeeManager);
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.set_paused(PauseFlags {
+            swap: true,
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "Owner role required")]
+    fn test_grant_role_not_owner() {
+        let context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = new_contract();
+        contract.grant_role(accounts(1), Role::Pauser);
+    }
+
+    #[test]
+    #[should_panic(expected = "not enough gas")]
+    fn test_withdraw_not_enough_gas() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = new_contract();
+        deposit(&mut contract, &mut context, accounts(1), accounts(3), 10);
+        testing_env!(context
+            .attached_deposit(1)
+            .prepaid_gas(Gas(15 * TGAS))
+            .build());
+        contract.withdraw(accounts(3), U128(10));
+    }
+
+    #[test]
+    fn test_withdraw_callback_failed() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = new_contract();
+        deposit(&mut contract, &mut context, accounts(1), accounts(3), 10);
+        testing_env!(context.attached_deposit(1).build());
+        contract.withdraw(accounts(3), U128(4));
+        assert_eq!(contract.get_deposit(accounts(1), accounts(3)).0, 6);
+
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            near_sdk::VMConfig::test(),
+            near_sdk::RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Failed],
+        );
+        contract.withdraw_callback(accounts(1), accounts(3), U128(4));
+        assert_eq!(contract.get_deposit(accounts(1), accounts(3)).0, 10);
+    }
+
+    #[test]
+    fn test_withdraw_callback_failed_after_unregister() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = new_contract();
+        deposit(&mut contract, &mut context, accounts(1), accounts(3), 10);
+        testing_env!(context.attached_deposit(1).build());
+        contract.withdraw(accounts(3), U128(10));
+        assert!(contract.storage_unregister(None));
+
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            near_sdk::VMConfig::test(),
+            near_sdk::RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Failed],
+        );
+        contract.withdraw_callback(accounts(1), accounts(3), U128(10));
+        assert_eq!(contract.get_lost_found(accounts(3)).0, 10);
+
+        register(&mut contract, &mut context, accounts(0));
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        assert_eq!(contract.claim_lost_found(accounts(3)).0, 10);
+        assert_eq!(contract.get_lost_found(accounts(3)).0, 0);
+        assert_eq!(contract.get_deposit(accounts(0), accounts(3)).0, 10);
+    }
+
+    #[test]
+    fn test_paused_swap() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = pool_contract(&mut context);
+        contract.set_paused(PauseFlags {
+            swap: true,
+            ..Default::default()
+        });
+        assert!(contract.get_paused().swap);
+        let result = contract.swap_token("TokenA".into(), U128(10), None, None);
+        assert_eq!(result.status, SwapStatus::Rejected);
+        assert_eq!(result.token_in, accounts(3));
+        assert_eq!(result.amount_out.0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "add liquidity is paused")]
+    fn test_paused_add_liquidity() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = new_contract();
+        contract.set_paused(PauseFlags {
+            add_liquidity: true,
+            ..Default::default()
+        });
+        contract.add_liquidity(0, vec![U128(10), U128(10)], None);
+    }
+
+    #[test]
+    fn test_paused_transfer_call_refund() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = new_contract();
+        contract.set_paused(PauseFlags {
+            add_liquidity: true,
+            ..Default::default()
+        });
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        match contract.ft_on_transfer(accounts(0), U128(10), "".into()) {
+            PromiseOrValue::Value(refund) => assert_eq!(refund.0, 10),
+            PromiseOrValue::Promise(_) => panic!("tokens should be refunded"),
         }
-        env::panic_str("get pool token failed")
+        assert_eq!(contract.get_deposit(accounts(0), accounts(3)).0, 0);
     }
 
-    /// withdraw balance to owner id, so that to change the ratio
-    #[private]
-    pub fn withdraw_token(&self, symbol: String, amount: U128) -> PromiseOrValue<U128> {
-        todo!("withdraw token from address_a or address_b")
-        // let gas = Gas(5 * TGAS);
-        // let token_address = self.get_contract_address(&symbol);
-        // let promise_withdraw: Promise = ext_ft_core::ext(token_address.clone())
-        //     .with_attached_deposit(1)
-        //     .with_static_gas(gas)
-        //     .ft_transfer_call(receiver_account, amount, None, "".into());
-        // promise_withdraw.into()
-    }
-
-    /// deposit token so that to change the ratio
-    #[private]
-    pub fn deposit_token(&self, symbol: String, amount: U128) -> PromiseOrValue<()> {
-        let gas = Gas(5 * TGAS);
-        let contract_address = self.get_contract_address(&symbol);
-        let token_address = self.get_token_address(&symbol);
+    #[test]
+    #[should_panic(expected = "E033: token not whitelisted")]
+    fn test_transfer_call_not_whitelisted() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = new_contract();
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.ft_on_transfer(accounts(0), U128(10), "".into());
+    }
 
-        let promise_deposit: Promise = ext_ft_core::ext(contract_address)
-            .with_attached_deposit(1)
-            .with_static_gas(gas)
-            .ft_transfer(token_address, amount, None);
-        promise_deposit.into()
+    #[test]
+    fn test_transfer_ownership() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = new_contract();
+        contract.propose_owner(accounts(1));
+        assert_eq!(contract.get_pending_owner(), Some(accounts(1)));
+        assert_eq!(contract.get_owner(), accounts(0));
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.accept_ownership();
+        assert_eq!(contract.get_owner(), accounts(1));
+        assert_eq!(contract.get_pending_owner(), None);
+        assert!(contract.has_role(accounts(1), Role::Pauser));
+        assert!(!contract.has_role(accounts(0), Role::Pauser));
     }
 
-    /// get balance ratio
-    #[private]
-    pub fn get_token_ratio(&self) -> PromiseOrValue<U128> {
-        let gas = Gas(5 * TGAS);
-        let contract_address_a = self.get_contract_address(&"TokenA".to_string());
-        let contract_address_b = self.get_contract_address(&"TokenB".to_string());
-        let promise_token_a = ext_ft_core::ext(contract_address_a)
-            .with_static_gas(gas)
-            .ft_balance_of(self.address_a.clone());
-        let promise_token_b = ext_ft_core::ext(contract_address_b)
-            .with_static_gas(gas)
-            .ft_balance_of(self.address_b.clone());
-        let promise_calculate_ratio = Self::ext(env::current_account_id())
-            .with_static_gas(gas)
-            .do_calculate_ratio();
-        promise_token_a
-            .and(promise_token_b)
-            .then(promise_calculate_ratio)
-            .into()
+    #[test]
+    #[should_panic(expected = "only the pending owner can accept the ownership")]
+    fn test_accept_ownership_not_pending_owner() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = new_contract();
+        contract.propose_owner(accounts(1));
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.accept_ownership();
     }
 
-    /// return  BalanceA * BalanceB
-    pub fn do_calculate_ratio(
-        &self,
-        #[callback_result] balance_a: Result<U128, PromiseError>,
-        #[callback_result] balance_b: Result<U128, PromiseError>,
-    ) -> U128 {
-        if let (Ok(balance_a), Ok(balance_b)) = (balance_a, balance_b) {
-            if let Some(result) = Balance::from(balance_a).checked_mul(Balance::from(balance_b)) {
-                U128::from(result)
-            } else {
-                env::panic_str("ratio is too large")
+    #[test]
+    #[should_panic(expected = "E009: fee is too large")]
+    fn test_set_fee_too_large() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = new_contract();
+        contract.set_fee(FEE_DIVISOR);
+    }
+
+    #[test]
+    fn test_storage_deposit_registration_only() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = new_contract();
+        let min = contract.storage_balance_bounds().min.0;
+        assert!(min > 0);
+        testing_env!(context.attached_deposit(min + 10).build());
+        let balance = contract.storage_deposit(None, Some(true));
+        assert_eq!(balance.total.0, min);
+        assert_eq!(balance.available.0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "E030: account not registered")]
+    fn test_transfer_call_not_registered() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = new_contract();
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        contract.ft_on_transfer(accounts(1), U128(10), "".into());
+    }
+
+    #[test]
+    #[should_panic(expected = "E031: not enough storage deposit")]
+    fn test_deposit_not_enough_storage() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = new_contract();
+        let min = contract.storage_balance_bounds().min.0;
+        testing_env!(context.attached_deposit(min).build());
+        contract.storage_deposit(None, None);
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(0)
+            .build());
+        contract.ft_on_transfer(accounts(1), U128(10), "".into());
+    }
+
+    #[test]
+    fn test_storage_tracks_deposits_and_unregister() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = new_contract();
+        deposit(&mut contract, &mut context, accounts(1), accounts(3), 10);
+        let balance = contract.storage_balance_of(accounts(1)).unwrap();
+        assert_eq!(balance.total.0, STORAGE_DEPOSIT);
+        let used = STORAGE_DEPOSIT - balance.available.0;
+        assert!(used > contract.storage_balance_bounds().min.0);
+
+        testing_env!(context.attached_deposit(1).build());
+        contract.withdraw(accounts(3), U128(10));
+        let balance = contract.storage_balance_of(accounts(1)).unwrap();
+        assert!(STORAGE_DEPOSIT - balance.available.0 < used);
+        assert!(contract.storage_unregister(None));
+        assert!(contract.storage_balance_of(accounts(1)).is_none());
+    }
+
+    #[test]
+    fn test_unregister_after_removing_shares() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = pool_contract(&mut context);
+        assert_eq!(
+            contract.internal_unwrap_account(&accounts(0)).pools,
+            [0].into()
+        );
+        let shares = contract.get_shares(0, accounts(0));
+        contract.remove_liquidity(0, shares, vec![U128(0), U128(0)], None);
+        assert!(contract
+            .internal_unwrap_account(&accounts(0))
+            .pools
+            .is_empty());
+        testing_env!(context.attached_deposit(1).build());
+        for token_id in [accounts(3), accounts(4)] {
+            contract.withdraw(token_id, U128(1000));
+        }
+        assert!(contract.storage_unregister(None));
+    }
+
+    #[test]
+    #[should_panic(expected = "E032: account has balances or shares")]
+    fn test_unregister_with_shares() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = pool_contract(&mut context);
+        testing_env!(context.attached_deposit(1).build());
+        for token_id in [accounts(3), accounts(4)] {
+            let balance = contract.get_deposit(accounts(0), token_id.clone());
+            if balance.0 > 0 {
+                contract.withdraw(token_id, balance);
             }
-        } else {
-            env::panic_str("get balance failed")
         }
+        contract.storage_unregister(None);
+    }
+
+    #[test]
+    fn test_whitelist_and_add_simple_pool() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = new_contract();
+        assert_eq!(
+            contract.get_whitelisted_tokens(None, None),
+            vec![accounts(3), accounts(4)]
+        );
+        contract.add_whitelisted_tokens(vec![accounts(5)]);
+        assert_eq!(
+            contract.get_whitelisted_tokens(Some(1), Some(5)),
+            vec![accounts(4), accounts(5)]
+        );
+
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(STORAGE_DEPOSIT)
+            .build());
+        let pool_id = contract.add_simple_pool(vec![accounts(3), accounts(5)], 25);
+        assert_eq!(pool_id, 1);
+        testing_env!(context.attached_deposit(0).build());
+        deposit(&mut contract, &mut context, accounts(1), accounts(3), 100);
+        deposit(&mut contract, &mut context, accounts(1), accounts(5), 100);
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.add_liquidity(pool_id, vec![U128(100), U128(100)], None);
+        assert!(contract.get_shares(pool_id, accounts(1)).0 > 0);
+        assert_eq!(contract.get_shares(0, accounts(1)).0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "E033: token not whitelisted")]
+    fn test_add_simple_pool_not_whitelisted() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = new_contract();
+        contract.remove_whitelisted_tokens(vec![accounts(4)]);
+        testing_env!(context.attached_deposit(STORAGE_DEPOSIT).build());
+        contract.add_simple_pool(vec![accounts(3), accounts(4)], 25);
+    }
+
+    #[test]
+    fn test_get_tokens_and_pools() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = pool_contract(&mut context);
+        let tokens = contract.get_tokens(None, None);
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[0].symbol, "TokenA");
+        let tokens = contract.get_tokens(Some(1), Some(10));
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].contract_address, accounts(4));
+
+        testing_env!(context.attached_deposit(STORAGE_DEPOSIT).build());
+        contract.add_simple_pool(vec![accounts(4), accounts(3)], 25);
+        let pools = contract.get_pools(None, None);
+        assert_eq!(pools.len(), 2);
+        assert_eq!(pools[0].amounts, vec![U128(1000), U128(1000)]);
+        let pools = contract.get_pools(Some(1), Some(1));
+        assert_eq!(pools[0].token_account_ids, vec![accounts(4), accounts(3)]);
+        assert_eq!(pools[0].total_fee, 25);
+        assert!(contract.get_pools(Some(2), None).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "E010: not enough gas, at least 30 Tgas is required")]
+    fn test_gas_config() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = new_contract();
+        assert_eq!(contract.get_required_gas("withdraw".into()), Gas(20 * TGAS));
+        contract.set_gas_config(GasConfig {
+            ft_transfer: Gas(15 * TGAS),
+            ..contract.get_gas_config()
+        });
+        assert_eq!(contract.get_required_gas("withdraw".into()), Gas(30 * TGAS));
+        assert_eq!(
+            contract.get_required_gas("set_token_info".into()),
+            Gas(20 * TGAS)
+        );
+
+        deposit(&mut contract, &mut context, accounts(1), accounts(3), 10);
+        testing_env!(context
+            .attached_deposit(1)
+            .prepaid_gas(Gas(25 * TGAS))
+            .build());
+        contract.withdraw(accounts(3), U128(10));
+    }
+
+    #[test]
+    #[should_panic(expected = "E036: unknown method")]
+    fn test_required_gas_unknown_method() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let contract = new_contract();
+        contract.get_required_gas("swap_token".into());
+    }
+
+    #[test]
+    fn test_swap_deadline_passed() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = pool_contract(&mut context);
+        deposit(&mut contract, &mut context, accounts(1), accounts(3), 100);
+        testing_env!(context.block_timestamp(2_000).build());
+        let result = contract.swap_token("TokenA".into(), U128(100), Some(U64(1_000)), None);
+        assert_eq!(result.status, SwapStatus::Rejected);
+        assert_eq!(contract.get_deposit(accounts(1), accounts(3)).0, 100);
+
+        let result = contract.swap_token("TokenA".into(), U128(100), Some(U64(2_000)), None);
+        assert_eq!(result.status, SwapStatus::Success);
+    }
+
+    #[test]
+    #[should_panic(expected = "E040: deadline has passed")]
+    fn test_add_liquidity_deadline_passed() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = pool_contract(&mut context);
+        testing_env!(context.block_timestamp(2_000).build());
+        contract.add_liquidity(0, vec![U128(10), U128(10)], Some(U64(1_000)));
+    }
+
+    #[test]
+    fn test_add_stable_swap_pool() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = new_contract();
+        testing_env!(context.attached_deposit(STORAGE_DEPOSIT).build());
+        let pool_id =
+            contract.add_stable_swap_pool(vec![accounts(3), accounts(4)], vec![6, 6], 5, 100);
+        testing_env!(context.attached_deposit(0).build());
+        deposit(
+            &mut contract,
+            &mut context,
+            accounts(0),
+            accounts(3),
+            1_000_000,
+        );
+        deposit(
+            &mut contract,
+            &mut context,
+            accounts(0),
+            accounts(4),
+            1_000_000,
+        );
+        contract.add_liquidity(pool_id, vec![U128(1_000_000), U128(1_000_000)], None);
+
+        let pool = contract.get_pool(pool_id);
+        assert_eq!(pool.pool_kind, "STABLE_SWAP");
+        assert_eq!(pool.amp_factor, Some(U128(100)));
+        let amount_out = contract.get_pool_return(pool_id, accounts(3), U128(1_000), accounts(4));
+        assert!(amount_out.0 > 990 && amount_out.0 < 1_000);
+
+        contract.ramp_amp(pool_id, 500, U64(2 * 86_400 * 1_000_000_000));
+        testing_env!(context.block_timestamp(2 * 86_400 * 1_000_000_000).build());
+        assert_eq!(contract.get_pool(pool_id).amp_factor, Some(U128(500)));
+    }
+
+    #[test]
+    #[should_panic(expected = "E043: not a stable swap pool")]
+    fn test_ramp_amp_simple_pool() {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = new_contract();
+        contract.ramp_amp(0, 500, U64(2 * 86_400 * 1_000_000_000));
+    }
+
+    #[test]
+    fn test_concentrated_pool_positions() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = new_contract();
+        testing_env!(context.attached_deposit(STORAGE_DEPOSIT).build());
+        let pool_id = contract.add_concentrated_pool(vec![accounts(3), accounts(4)], 30, 10, 0);
+        testing_env!(context.attached_deposit(0).build());
+        deposit(
+            &mut contract,
+            &mut context,
+            accounts(0),
+            accounts(3),
+            100_000,
+        );
+        deposit(
+            &mut contract,
+            &mut context,
+            accounts(0),
+            accounts(4),
+            100_000,
+        );
+        let position_id =
+            contract.add_position(pool_id, -100, 100, vec![U128(100_000), U128(100_000)], None);
+        assert_eq!(
+            contract.get_pool(pool_id).pool_kind,
+            "CONCENTRATED_LIQUIDITY"
+        );
+        assert_eq!(contract.get_pool(pool_id).current_tick, Some(0));
+
+        deposit(
+            &mut contract,
+            &mut context,
+            accounts(1),
+            accounts(3),
+            10_000,
+        );
+        let result = contract.swap(pool_id, accounts(3), U128(10_000), accounts(4), None);
+        assert_eq!(result.status, SwapStatus::Success);
+        assert_eq!(
+            contract.get_deposit(accounts(1), accounts(4)),
+            result.amount_out
+        );
+
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        let positions = contract.get_account_positions(pool_id, accounts(0));
+        assert_eq!(positions.len(), 1);
+        assert!(positions[0].unclaimed_fees[0].0 > 0);
+        let fees = contract.claim_position_fees(pool_id, position_id);
+        assert_eq!(fees, positions[0].unclaimed_fees);
+
+        let liquidity = contract.get_shares(pool_id, accounts(0));
+        contract.remove_position(
+            pool_id,
+            position_id,
+            liquidity,
+            vec![U128(0), U128(0)],
+            None,
+        );
+        assert!(contract.get_position(pool_id, position_id).is_none());
+        assert_eq!(contract.get_shares(pool_id, accounts(0)).0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "E050: pool has positions instead of shares")]
+    fn test_add_liquidity_concentrated_pool() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = new_contract();
+        testing_env!(context.attached_deposit(STORAGE_DEPOSIT).build());
+        let pool_id = contract.add_concentrated_pool(vec![accounts(3), accounts(4)], 30, 10, 0);
+        testing_env!(context.attached_deposit(0).build());
+        contract.add_liquidity(pool_id, vec![U128(10), U128(10)], None);
+    }
+
+    #[test]
+    fn test_flash_swap_repaid() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = pool_contract(&mut context);
+        register(&mut contract, &mut context, accounts(2));
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .prepaid_gas(Gas(300 * TGAS))
+            .build());
+        contract.flash_swap(0, accounts(4), U128(500), "".into());
+        assert_eq!(contract.get_pool(0).amounts[1].0, 500);
+        assert_eq!(contract.get_deposit(accounts(2), accounts(4)).0, 500);
+        assert_eq!(contract.get_flash_swap(accounts(2)).unwrap().fee.0, 1);
+
+        // the receiver is one short, what it holds is clawed back, the fee first
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        assert!(!contract.resolve_flash_swap(accounts(2)));
+        assert_eq!(contract.get_pool(0).amounts[1].0, 1000);
+        assert_eq!(contract.get_deposit(accounts(2), accounts(4)).0, 0);
+        let flash_swap = contract.get_flash_swap(accounts(2)).unwrap();
+        assert_eq!((flash_swap.amount.0, flash_swap.fee.0), (1, 0));
+        // the pool stays locked while the loan is short
+        assert!(contract.pool_has_flash_swap(0));
+
+        deposit(&mut contract, &mut context, accounts(2), accounts(4), 1);
+        assert!(contract.repay_flash_swap());
+        assert_eq!(contract.get_pool(0).amounts[1].0, 1001);
+        assert_eq!(contract.get_deposit(accounts(2), accounts(4)).0, 0);
+        assert!(contract.get_flash_swap(accounts(2)).is_none());
+        assert!(!contract.pool_has_flash_swap(0));
+    }
+
+    #[test]
+    fn test_flash_swap_not_repaid() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = pool_contract(&mut context);
+        register(&mut contract, &mut context, accounts(2));
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .prepaid_gas(Gas(300 * TGAS))
+            .build());
+        contract.flash_swap(0, accounts(4), U128(500), "".into());
+
+        // nothing is paid back, the pool keeps refusing swaps and liquidity changes
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.internal_withdraw(&accounts(2), &accounts(4), 500);
+        assert!(!contract.resolve_flash_swap(accounts(2)));
+        assert_eq!(contract.get_pool(0).amounts[1].0, 500);
+        deposit(&mut contract, &mut context, accounts(1), accounts(3), 100);
+        let result = contract.swap(0, accounts(3), U128(100), accounts(4), None);
+        assert_eq!(result.status, SwapStatus::Rejected);
+
+        deposit(&mut contract, &mut context, accounts(2), accounts(4), 501);
+        assert!(contract.repay_flash_swap());
+        assert_eq!(contract.get_pool(0).amounts[1].0, 1001);
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        let result = contract.swap(0, accounts(3), U128(100), accounts(4), None);
+        assert_eq!(result.status, SwapStatus::Success);
+    }
+
+    #[test]
+    #[should_panic(expected = "E056: flash swap in progress")]
+    fn test_swap_borrowed_tokens() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = pool_contract(&mut context);
+        register(&mut contract, &mut context, accounts(2));
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .prepaid_gas(Gas(300 * TGAS))
+            .build());
+        contract.flash_swap(0, accounts(4), U128(500), "".into());
+        contract.swap(0, accounts(4), U128(500), accounts(3), None);
+    }
+
+    #[test]
+    fn test_pool_locked_during_flash_swap() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = pool_contract(&mut context);
+        register(&mut contract, &mut context, accounts(2));
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .prepaid_gas(Gas(300 * TGAS))
+            .build());
+        contract.flash_swap(0, accounts(4), U128(500), "".into());
+
+        deposit(&mut contract, &mut context, accounts(1), accounts(3), 100);
+        let result = contract.swap(0, accounts(3), U128(100), accounts(4), None);
+        assert_eq!(result.status, SwapStatus::Rejected);
+        assert_eq!(contract.get_deposit(accounts(1), accounts(3)).0, 100);
+
+        deposit(&mut contract, &mut context, accounts(2), accounts(4), 1);
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        assert!(contract.resolve_flash_swap(accounts(2)));
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        let result = contract.swap(0, accounts(3), U128(100), accounts(4), None);
+        assert_eq!(result.status, SwapStatus::Success);
+    }
+
+    #[test]
+    #[should_panic(expected = "E056: flash swap in progress")]
+    fn test_add_liquidity_during_flash_swap() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = pool_contract(&mut context);
+        register(&mut contract, &mut context, accounts(2));
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .prepaid_gas(Gas(300 * TGAS))
+            .build());
+        contract.flash_swap(0, accounts(4), U128(500), "".into());
+        deposit(&mut contract, &mut context, accounts(0), accounts(3), 10);
+        deposit(&mut contract, &mut context, accounts(0), accounts(4), 10);
+        contract.add_liquidity(0, vec![U128(10), U128(10)], None);
+    }
+
+    #[test]
+    #[should_panic(expected = "E063: price move is too large")]
+    fn test_flash_swap_circuit_breaker() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = pool_contract(&mut context);
+        contract.set_circuit_breaker(CircuitBreaker {
+            max_price_impact: 1000,
+            ..Default::default()
+        });
+        register(&mut contract, &mut context, accounts(2));
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .prepaid_gas(Gas(300 * TGAS))
+            .build());
+        contract.flash_swap(0, accounts(4), U128(500), "".into());
+    }
+
+    #[test]
+    #[should_panic(expected = "E056: flash swap in progress")]
+    fn test_withdraw_during_flash_swap() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = pool_contract(&mut context);
+        register(&mut contract, &mut context, accounts(2));
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .prepaid_gas(Gas(300 * TGAS))
+            .build());
+        contract.flash_swap(0, accounts(4), U128(500), "".into());
+        testing_env!(context.attached_deposit(1).build());
+        contract.withdraw(accounts(4), U128(500));
     }
 }
@@ -0,0 +1,360 @@
+use near_sdk::collections::{LookupMap, UnorderedMap};
+
+use crate::errors::ensure;
+use crate::events::{LimitOrderCancelled, LimitOrderFilled, LimitOrderPlaced};
+use crate::utils::{paginate, U256};
+use crate::*;
+
+/// order to sell `amount_in` of `token_in` for at least `min_amount_out` of `token_out`,
+/// the limit price `min_amount_out / amount_in` is kept by partial fills
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct LimitOrder {
+    pub order_id: U64,
+    pub owner_id: AccountId,
+    /// pool the order is executed against by `execute_orders`
+    pub pool_id: u64,
+    pub token_in: AccountId,
+    /// escrowed `token_in` left to sell
+    pub amount_in: U128,
+    pub token_out: AccountId,
+    /// `token_out` to receive at least for the rest of the order
+    pub min_amount_out: U128,
+}
+
+/// open limit orders, the escrowed tokens are held by the book and not by the accounts
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct LimitOrderBook {
+    orders: UnorderedMap<u64, LimitOrder>,
+    /// (account, order ids) map
+    account_orders: LookupMap<AccountId, Vec<u64>>,
+    next_order_id: u64,
+}
+
+impl LimitOrderBook {
+    pub(crate) fn new() -> Self {
+        Self {
+            orders: UnorderedMap::new(StoreKey::LimitOrders),
+            account_orders: LookupMap::new(StoreKey::AccountLimitOrders),
+            next_order_id: 0,
+        }
+    }
+
+    fn get(&self, order_id: u64) -> LimitOrder {
+        self.orders
+            .get(&order_id)
+            .unwrap_or_else(|| DeFiError::OrderNotFound.panic())
+    }
+
+    fn next_id(&mut self) -> u64 {
+        self.next_order_id += 1;
+        self.next_order_id - 1
+    }
+
+    fn insert(&mut self, order: &LimitOrder) {
+        if self.orders.insert(&order.order_id.0, order).is_none() {
+            let mut order_ids = self.account_orders.get(&order.owner_id).unwrap_or_default();
+            order_ids.push(order.order_id.0);
+            self.account_orders.insert(&order.owner_id, &order_ids);
+        }
+    }
+
+    fn remove(&mut self, order: &LimitOrder) {
+        self.orders.remove(&order.order_id.0);
+        let mut order_ids = self.account_orders.get(&order.owner_id).unwrap_or_default();
+        order_ids.retain(|id| *id != order.order_id.0);
+        if order_ids.is_empty() {
+            self.account_orders.remove(&order.owner_id);
+        } else {
+            self.account_orders.insert(&order.owner_id, &order_ids);
+        }
+    }
+
+    pub(crate) fn has_orders(&self, account_id: &AccountId) -> bool {
+        self.account_orders.contains_key(account_id)
+    }
+}
+
+#[near_bindgen]
+impl DeFi {
+    /// escrow `amount_in` of `token_in` from the caller's internal balance to sell it for at
+    /// least `min_amount_out` of `token_out`. return the id of the order
+    pub fn place_limit_order(
+        &mut self,
+        pool_id: u64,
+        token_in: AccountId,
+        amount_in: U128,
+        token_out: AccountId,
+        min_amount_out: U128,
+    ) -> U64 {
+        ensure(
+            amount_in.0 > 0 && min_amount_out.0 > 0,
+            DeFiError::ZeroAmount,
+        );
+        let pool = self.internal_get_pool(pool_id);
+        ensure(
+            token_in != token_out
+                && pool.token_index(&token_in).is_some()
+                && pool.token_index(&token_out).is_some(),
+            DeFiError::UnknownToken,
+        );
+        let account_id = env::predecessor_account_id();
+        // an accomplice could take the order and keep the borrowed value
+        self.assert_no_flash_swap(&account_id);
+        let prev_storage = env::storage_usage();
+        self.internal_withdraw(&account_id, &token_in, amount_in.0);
+        let order = LimitOrder {
+            order_id: U64(self.limit_orders.next_id()),
+            owner_id: account_id.clone(),
+            pool_id,
+            token_in,
+            amount_in,
+            token_out,
+            min_amount_out,
+        };
+        self.limit_orders.insert(&order);
+        self.internal_update_storage(&account_id, prev_storage);
+        LimitOrderPlaced {
+            order_id: &order.order_id,
+            account_id: &account_id,
+            pool_id,
+            token_in: &order.token_in,
+            amount_in: &order.amount_in,
+            token_out: &order.token_out,
+            min_amount_out: &order.min_amount_out,
+        }
+        .emit();
+        order.order_id
+    }
+
+    /// cancel the caller's order, the rest of the escrow is credited back.
+    /// return the amount credited
+    pub fn cancel_limit_order(&mut self, order_id: U64) -> U128 {
+        let order = self.limit_orders.get(order_id.0);
+        let account_id = env::predecessor_account_id();
+        ensure(order.owner_id == account_id, DeFiError::NotOrderOwner);
+        let prev_storage = env::storage_usage();
+        self.limit_orders.remove(&order);
+        self.internal_deposit(&account_id, &order.token_in, order.amount_in.0);
+        self.internal_update_storage(&account_id, prev_storage);
+        LimitOrderCancelled {
+            order_id: &order_id,
+            account_id: &account_id,
+            amount_in: &order.amount_in,
+        }
+        .emit();
+        order.amount_in
+    }
+
+    /// buy `amount_in` of the escrowed `token_in` of the order at its limit price, paid with
+    /// `token_out` from the caller's internal balance. return the amount paid
+    pub fn take_limit_order(&mut self, order_id: U64, amount_in: U128) -> U128 {
+        ensure(!self.paused.swap, DeFiError::SwapPaused);
+        let mut order = self.limit_orders.get(order_id.0);
+        let account_id = env::predecessor_account_id();
+        // the pool of the order and the borrowed value stay out of reach until the loan is paid
+        self.assert_no_flash_swap(&account_id);
+        self.assert_no_pool_flash_swap(order.pool_id);
+        ensure(amount_in.0 > 0, DeFiError::ZeroAmount);
+        ensure(amount_in.0 <= order.amount_in.0, DeFiError::AmountTooLarge);
+        // rounded up in favor of the order owner
+        let amount_out = (U256::from(amount_in.0) * U256::from(order.min_amount_out.0)
+            + U256::from(order.amount_in.0 - 1))
+            / U256::from(order.amount_in.0);
+        let amount_out = amount_out.as_u128();
+
+        let prev_storage = env::storage_usage();
+        order.amount_in = U128(order.amount_in.0 - amount_in.0);
+        order.min_amount_out = U128(order.min_amount_out.0.saturating_sub(amount_out));
+        if order.amount_in.0 == 0 {
+            self.limit_orders.remove(&order);
+        } else {
+            self.limit_orders.insert(&order);
+        }
+        self.internal_deposit(&order.owner_id, &order.token_out, amount_out);
+        self.internal_track_storage(&order.owner_id, prev_storage);
+
+        let prev_storage = env::storage_usage();
+        self.internal_withdraw(&account_id, &order.token_out, amount_out);
+        self.internal_deposit(&account_id, &order.token_in, amount_in.0);
+        self.internal_update_storage(&account_id, prev_storage);
+        LimitOrderFilled {
+            order_id: &order_id,
+            account_id: &order.owner_id,
+            taker_id: Some(&account_id),
+            amount_in: &amount_in,
+            amount_out: &U128(amount_out),
+        }
+        .emit();
+        U128(amount_out)
+    }
+
+    /// swap every order of `order_ids` whose pool returns at least its `min_amount_out`,
+    /// the rest, including the orders the pool can't fill at all, is skipped. anyone can call it, e.g. a keeper watching the prices.
+    /// return the ids of the executed orders
+    pub fn execute_orders(&mut self, order_ids: Vec<U64>) -> Vec<U64> {
+        ensure(!self.paused.swap, DeFiError::SwapPaused);
+        let mut executed = vec![];
+        for order_id in order_ids {
+            let order = match self.limit_orders.orders.get(&order_id.0) {
+                Some(order) => order,
+                None => continue,
+            };
+            if self.pool_has_flash_swap(order.pool_id) {
+                continue;
+            }
+            let mut pool = self.internal_get_pool(order.pool_id);
+            // a pool which can't fill the order, e.g. short of liquidity, is skipped too
+            let amount_out =
+                match pool.try_swap_return(&order.token_in, order.amount_in.0, &order.token_out) {
+                    Ok(amount_out) if amount_out >= order.min_amount_out.0 => amount_out,
+                    _ => continue,
+                };
+            let moved = match self.check_price_move(
+                order.pool_id,
+                &pool,
+                &order.token_in,
+                order.amount_in.0,
+                &order.token_out,
+            ) {
+                Ok(moved) => moved,
+                Err(_) => continue,
+            };
+            self.internal_update_oracle(order.pool_id, &pool);
+            pool.swap(&order.token_in, order.amount_in.0, &order.token_out);
+            self.pools.replace(order.pool_id, &pool);
+            self.record_price_move(order.pool_id, moved);
+
+            let prev_storage = env::storage_usage();
+            self.limit_orders.remove(&order);
+            self.internal_deposit(&order.owner_id, &order.token_out, amount_out);
+            // the keeper can't be stopped by the storage of the owner
+            self.internal_track_storage(&order.owner_id, prev_storage);
+            LimitOrderFilled {
+                order_id: &order_id,
+                account_id: &order.owner_id,
+                taker_id: None,
+                amount_in: &order.amount_in,
+                amount_out: &U128(amount_out),
+            }
+            .emit();
+            executed.push(order_id);
+        }
+        executed
+    }
+
+    pub fn get_limit_order(&self, order_id: U64) -> Option<LimitOrder> {
+        self.limit_orders.orders.get(&order_id.0)
+    }
+
+    pub fn get_limit_orders(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<LimitOrder> {
+        paginate(
+            self.limit_orders.orders.values_as_vector(),
+            from_index,
+            limit,
+        )
+    }
+
+    pub fn get_account_limit_orders(&self, account_id: AccountId) -> Vec<LimitOrder> {
+        self.limit_orders
+            .account_orders
+            .get(&account_id)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|order_id| self.limit_orders.orders.get(&order_id))
+            .collect()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::test_utils::{deposit, pool_contract, STORAGE_DEPOSIT};
+
+    fn contract() -> (DeFi, VMContextBuilder) {
+        pool_contract(1000)
+    }
+
+    #[test]
+    fn place_and_cancel() {
+        let (mut contract, mut context) = contract();
+        deposit(&mut contract, &mut context, accounts(1), accounts(3), 100);
+        let order_id = contract.place_limit_order(0, accounts(3), U128(100), accounts(4), U128(95));
+        assert_eq!(contract.get_deposit(accounts(1), accounts(3)).0, 0);
+        assert_eq!(contract.get_account_limit_orders(accounts(1)).len(), 1);
+        assert_eq!(contract.get_limit_orders(None, None).len(), 1);
+
+        assert_eq!(contract.cancel_limit_order(order_id).0, 100);
+        assert_eq!(contract.get_deposit(accounts(1), accounts(3)).0, 100);
+        assert!(contract.get_limit_order(order_id).is_none());
+        assert!(contract.get_account_limit_orders(accounts(1)).is_empty());
+    }
+
+    #[test]
+    fn take_partially() {
+        let (mut contract, mut context) = contract();
+        deposit(&mut contract, &mut context, accounts(1), accounts(3), 100);
+        let order_id = contract.place_limit_order(0, accounts(3), U128(100), accounts(4), U128(50));
+
+        deposit(&mut contract, &mut context, accounts(2), accounts(4), 100);
+        assert_eq!(contract.take_limit_order(order_id, U128(40)).0, 20);
+        assert_eq!(contract.get_deposit(accounts(2), accounts(3)).0, 40);
+        assert_eq!(contract.get_deposit(accounts(2), accounts(4)).0, 80);
+        assert_eq!(contract.get_deposit(accounts(1), accounts(4)).0, 20);
+        let order = contract.get_limit_order(order_id).unwrap();
+        assert_eq!((order.amount_in.0, order.min_amount_out.0), (60, 30));
+
+        contract.take_limit_order(order_id, U128(60));
+        assert!(contract.get_limit_order(order_id).is_none());
+        assert_eq!(contract.get_deposit(accounts(1), accounts(4)).0, 50);
+    }
+
+    #[test]
+    fn execute_when_price_crosses() {
+        let (mut contract, mut context) = contract();
+        deposit(&mut contract, &mut context, accounts(1), accounts(3), 100);
+        // the pool returns 90 for 100 now
+        let order_id =
+            contract.place_limit_order(0, accounts(3), U128(100), accounts(4), U128(100));
+        assert!(contract.execute_orders(vec![order_id]).is_empty());
+
+        // someone buys TokenA, its price goes up
+        deposit(&mut contract, &mut context, accounts(2), accounts(4), 500);
+        contract.swap(0, accounts(4), U128(500), accounts(3), None);
+
+        assert_eq!(
+            contract.execute_orders(vec![order_id, U64(7)]),
+            vec![order_id]
+        );
+        assert!(contract.get_limit_order(order_id).is_none());
+        assert!(contract.get_deposit(accounts(1), accounts(4)).0 >= 100);
+    }
+
+    #[test]
+    fn skip_unfillable_orders() {
+        let (mut contract, mut context) = contract();
+        testing_env!(context.attached_deposit(STORAGE_DEPOSIT).build());
+        let empty_pool_id = contract.add_simple_pool(vec![accounts(3), accounts(4)], 0);
+        deposit(&mut contract, &mut context, accounts(1), accounts(3), 200);
+        let unfillable =
+            contract.place_limit_order(empty_pool_id, accounts(3), U128(100), accounts(4), U128(1));
+        let order_id = contract.place_limit_order(0, accounts(3), U128(100), accounts(4), U128(80));
+        assert_eq!(
+            contract.execute_orders(vec![unfillable, order_id]),
+            vec![order_id]
+        );
+        assert!(contract.get_limit_order(unfillable).is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "E054: not the owner of the order")]
+    fn cancel_order_of_other_account() {
+        let (mut contract, mut context) = contract();
+        deposit(&mut contract, &mut context, accounts(1), accounts(3), 100);
+        let order_id = contract.place_limit_order(0, accounts(3), U128(100), accounts(4), U128(95));
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.cancel_limit_order(order_id);
+    }
+}
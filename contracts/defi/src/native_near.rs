@@ -0,0 +1,429 @@
+use near_sdk::{assert_one_yocto, PromiseResult};
+
+use crate::errors::ensure;
+use crate::events::{Deposit, Refund, Withdraw};
+use crate::*;
+
+/// interface of the wNEAR contract
+#[ext_contract(ext_wnear)]
+pub trait WrappedNear {
+    /// mint wNEAR for the attached NEAR to the caller
+    fn near_deposit(&mut self);
+
+    /// burn `amount` of the caller's wNEAR and send the NEAR back, requires 1 yoctoNEAR
+    fn near_withdraw(&mut self, amount: U128) -> Promise;
+}
+
+#[near_bindgen]
+impl DeFi {
+    /// set the wNEAR contract, only the owner can call it. this contract must be registered
+    /// with it, and it must be whitelisted to be pooled
+    pub fn set_wnear(&mut self, wnear_id: AccountId) {
+        self.assert_role(Role::Owner);
+        self.wnear_id = Some(wnear_id);
+    }
+
+    pub fn get_wnear(&self) -> Option<AccountId> {
+        self.wnear_id.clone()
+    }
+
+    /// wrap the attached NEAR and swap it for `token_out` in the pool `pool_id`, panics if
+    /// the block timestamp is past `deadline` (nanoseconds). the NEAR is sent back if the
+    /// wrapping fails or the swap is rejected, the wNEAR stays in the caller's internal
+    /// balance if the swap fails. a registered `referral_id` earns the referral share of
+    /// the fee
+    #[payable]
+    pub fn swap_near(
+        &mut self,
+        pool_id: u64,
+        token_out: AccountId,
+        deadline: Option<U64>,
+        referral_id: Option<AccountId>,
+    ) -> Promise {
+        self.assert_enough_gas("swap_near");
+        assert_deadline(deadline);
+        let (account_id, wnear_id, amount) = self.assert_near_deposit();
+        self.assert_no_flash_swap(&account_id);
+        // `internal_swap` would reject it in the callback, after the NEAR is wrapped
+        ensure(token_out != wnear_id, DeFiError::SameToken);
+        self.assert_referral(&account_id, referral_id.as_ref());
+        let options = SwapOptions {
+            deadline,
+            unwrap_near: false,
+            referral_id,
+        };
+        self.internal_wrap_near(&account_id, &wnear_id, amount)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(self.gas_config.required("withdraw_near"))
+                    .swap_near_callback(account_id, pool_id, amount, token_out, options),
+            )
+    }
+
+    /// swap the wrapped NEAR once it is in the internal balance, unwrap it and send the NEAR
+    /// back if the swap is rejected
+    #[private]
+    pub fn swap_near_callback(
+        &mut self,
+        account_id: AccountId,
+        pool_id: u64,
+        amount_in: U128,
+        token_out: AccountId,
+        options: SwapOptions,
+        #[callback_result] wrapped: Result<bool, PromiseError>,
+    ) -> SwapResult {
+        ensure(matches!(wrapped, Ok(true)), DeFiError::NearWrapFailed);
+        let wnear_id = self.internal_unwrap_wnear();
+        let result = self.internal_swap(
+            &account_id,
+            pool_id,
+            wnear_id,
+            amount_in,
+            token_out,
+            &options,
+        );
+        if result.status == SwapStatus::Rejected {
+            if self.paused.withdraw {
+                log!("the wNEAR stays in the internal balance");
+            } else {
+                self.internal_withdraw_near(&account_id, amount_in);
+            }
+        }
+        result
+    }
+
+    /// wrap the attached NEAR and add liquidity to the pool `pool_id` like `add_liquidity`,
+    /// the wNEAR in `amounts` is taken from the internal balance once the NEAR is credited
+    /// there. panics if the block timestamp is past `deadline` (nanoseconds), the wNEAR stays
+    /// in the internal balance if adding the liquidity fails later
+    #[payable]
+    pub fn add_liquidity_near(
+        &mut self,
+        pool_id: u64,
+        amounts: Vec<U128>,
+        deadline: Option<U64>,
+    ) -> Promise {
+        self.assert_enough_gas("add_liquidity_near");
+        assert_deadline(deadline);
+        let (account_id, wnear_id, amount) = self.assert_near_deposit();
+        self.internal_wrap_near(&account_id, &wnear_id, amount)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(self.gas_config.entry)
+                    .add_liquidity_near_callback(account_id, pool_id, amounts, deadline),
+            )
+    }
+
+    /// add the liquidity once the wrapped NEAR is in the internal balance
+    #[private]
+    pub fn add_liquidity_near_callback(
+        &mut self,
+        account_id: AccountId,
+        pool_id: u64,
+        amounts: Vec<U128>,
+        deadline: Option<U64>,
+        #[callback_result] wrapped: Result<bool, PromiseError>,
+    ) -> U128 {
+        ensure(matches!(wrapped, Ok(true)), DeFiError::NearWrapFailed);
+        self.internal_add_liquidity(&account_id, pool_id, amounts, deadline)
+    }
+
+    /// credit the wrapped NEAR to the internal balance, or send the NEAR back if the
+    /// wrapping failed. the wNEAR of an account which unregistered meanwhile goes to
+    /// the lost and found balance. return whether it was credited
+    #[private]
+    pub fn near_deposit_callback(
+        &mut self,
+        account_id: AccountId,
+        wnear_id: AccountId,
+        amount: U128,
+    ) -> bool {
+        match env::promise_result(0) {
+            PromiseResult::Successful(_) => {
+                let credited = self.internal_deposit_or_lost_found(
+                    &account_id,
+                    &wnear_id,
+                    amount.0,
+                    "account unregistered",
+                );
+                if credited {
+                    Deposit {
+                        account_id: &account_id,
+                        token_id: &wnear_id,
+                        amount: &amount,
+                    }
+                    .emit();
+                }
+                credited
+            }
+            _ => {
+                Promise::new(account_id.clone()).transfer(amount.0);
+                Refund {
+                    account_id: &account_id,
+                    token_id: &wnear_id,
+                    amount: &amount,
+                    reason: "wrapping NEAR failed",
+                }
+                .emit();
+                false
+            }
+        }
+    }
+
+    /// unwrap `amount` of wNEAR from the internal balance and send the NEAR to the caller
+    #[payable]
+    pub fn withdraw_near(&mut self, amount: U128) -> Promise {
+        assert_one_yocto();
+        ensure(amount.0 > 0, DeFiError::ZeroAmount);
+        self.assert_enough_gas("withdraw_near");
+        self.internal_withdraw_near(&env::predecessor_account_id(), amount)
+    }
+
+    /// send the unwrapped NEAR, or put the wNEAR back to the internal balance
+    /// if the unwrapping failed
+    #[private]
+    pub fn near_withdraw_callback(
+        &mut self,
+        account_id: AccountId,
+        wnear_id: AccountId,
+        amount: U128,
+    ) {
+        match env::promise_result(0) {
+            PromiseResult::Successful(_) => {
+                Promise::new(account_id.clone()).transfer(amount.0);
+                Withdraw {
+                    account_id: &account_id,
+                    token_id: &wnear_id,
+                    amount: &amount,
+                }
+                .emit();
+            }
+            _ => self.internal_refund(&account_id, &wnear_id, amount, "unwrapping NEAR failed"),
+        }
+    }
+}
+
+impl DeFi {
+    fn internal_unwrap_wnear(&self) -> AccountId {
+        self.wnear_id
+            .clone()
+            .unwrap_or_else(|| DeFiError::WNearNotConfigured.panic())
+    }
+
+    /// the caller, the wNEAR contract and the attached NEAR, which can be deposited
+    fn assert_near_deposit(&self) -> (AccountId, AccountId, U128) {
+        let wnear_id = self.internal_unwrap_wnear();
+        ensure(!self.paused.add_liquidity, DeFiError::AddLiquidityPaused);
+        self.assert_whitelisted(&wnear_id);
+        let amount = env::attached_deposit();
+        ensure(amount > 0, DeFiError::ZeroAmount);
+        let account_id = env::predecessor_account_id();
+        self.internal_unwrap_account(&account_id);
+        (account_id, wnear_id, U128(amount))
+    }
+
+    /// wrap `amount` of NEAR held by this contract, the callback credits it to `account_id`
+    fn internal_wrap_near(
+        &self,
+        account_id: &AccountId,
+        wnear_id: &AccountId,
+        amount: U128,
+    ) -> Promise {
+        ext_wnear::ext(wnear_id.clone())
+            .with_attached_deposit(amount.0)
+            .with_static_gas(self.gas_config.ft_transfer)
+            .near_deposit()
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(self.gas_config.callback)
+                    .near_deposit_callback(account_id.clone(), wnear_id.clone(), amount),
+            )
+    }
+
+    /// unwrap `amount` of wNEAR from the internal balance of `account_id`,
+    /// the callback sends the NEAR
+    pub(crate) fn internal_withdraw_near(
+        &mut self,
+        account_id: &AccountId,
+        amount: U128,
+    ) -> Promise {
+        ensure(!self.paused.withdraw, DeFiError::WithdrawPaused);
+        self.assert_no_flash_swap(account_id);
+        let wnear_id = self.internal_unwrap_wnear();
+        let prev_storage = env::storage_usage();
+        self.internal_withdraw(account_id, &wnear_id, amount.0);
+        self.internal_update_storage(account_id, prev_storage);
+        ext_wnear::ext(wnear_id.clone())
+            .with_attached_deposit(1)
+            .with_static_gas(self.gas_config.ft_transfer)
+            .near_withdraw(amount)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(self.gas_config.callback)
+                    .near_withdraw_callback(account_id.clone(), wnear_id, amount),
+            )
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, RuntimeFeesConfig, VMConfig};
+
+    use super::*;
+    use crate::test_utils::{deposit, new_contract, register, STORAGE_DEPOSIT};
+
+    /// wNEAR is `accounts(5)`, pool 1 has 1000 wNEAR and 1000 TokenB
+    fn contract() -> (DeFi, VMContextBuilder) {
+        let (mut contract, mut context) = new_contract();
+        testing_env!(context.prepaid_gas(Gas(300 * TGAS)).build());
+        contract.add_whitelisted_tokens(vec![accounts(5)]);
+        contract.set_wnear(accounts(5));
+        testing_env!(context.attached_deposit(STORAGE_DEPOSIT).build());
+        contract.add_simple_pool(vec![accounts(5), accounts(4)], 0);
+        deposit(&mut contract, &mut context, accounts(0), accounts(5), 1000);
+        deposit(&mut contract, &mut context, accounts(0), accounts(4), 1000);
+        contract.add_liquidity(1, vec![U128(1000), U128(1000)], None);
+        register(&mut contract, &mut context, accounts(1));
+        (contract, context)
+    }
+
+    /// run the next step of the promise chain with `result` as the result of the previous one
+    fn callback(context: &mut VMContextBuilder, result: PromiseResult) {
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![result],
+        );
+    }
+
+    #[test]
+    fn swap_near() {
+        let (mut contract, mut context) = contract();
+        testing_env!(context.attached_deposit(100).build());
+        contract.swap_near(1, accounts(4), None, None);
+
+        callback(&mut context, PromiseResult::Successful(vec![]));
+        assert!(contract.near_deposit_callback(accounts(1), accounts(5), U128(100)));
+        assert_eq!(contract.get_deposit(accounts(1), accounts(5)).0, 100);
+
+        let result = contract.swap_near_callback(
+            accounts(1),
+            1,
+            U128(100),
+            accounts(4),
+            SwapOptions::default(),
+            Ok(true),
+        );
+        assert_eq!(result.status, SwapStatus::Success);
+        assert_eq!(result.amount_out.0, 90);
+        assert_eq!(contract.get_deposit(accounts(1), accounts(5)).0, 0);
+        assert_eq!(contract.get_deposit(accounts(1), accounts(4)).0, 90);
+    }
+
+    #[test]
+    fn swap_near_rejected() {
+        let (mut contract, mut context) = contract();
+        callback(&mut context, PromiseResult::Successful(vec![]));
+        contract.near_deposit_callback(accounts(1), accounts(5), U128(100));
+        contract.set_paused(PauseFlags {
+            swap: true,
+            ..Default::default()
+        });
+        let result = contract.swap_near_callback(
+            accounts(1),
+            1,
+            U128(100),
+            accounts(4),
+            SwapOptions::default(),
+            Ok(true),
+        );
+        assert_eq!(result.status, SwapStatus::Rejected);
+        // the wNEAR is on its way back to the wNEAR contract to be unwrapped
+        assert_eq!(contract.get_deposit(accounts(1), accounts(5)).0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "E040: deadline has passed")]
+    fn swap_near_deadline_passed() {
+        let (mut contract, mut context) = contract();
+        testing_env!(context.attached_deposit(100).block_timestamp(2_000).build());
+        contract.swap_near(1, accounts(4), Some(U64(1_000)), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "E040: deadline has passed")]
+    fn add_liquidity_near_deadline_passed() {
+        let (mut contract, mut context) = contract();
+        testing_env!(context.attached_deposit(100).block_timestamp(2_000).build());
+        contract.add_liquidity_near(1, vec![U128(100), U128(100)], Some(U64(1_000)));
+    }
+
+    #[test]
+    #[should_panic(expected = "E060: wrapping NEAR failed")]
+    fn wrap_failed() {
+        let (mut contract, mut context) = contract();
+        callback(&mut context, PromiseResult::Failed);
+        assert!(!contract.near_deposit_callback(accounts(1), accounts(5), U128(100)));
+        assert_eq!(contract.get_deposit(accounts(1), accounts(5)).0, 0);
+        contract.swap_near_callback(
+            accounts(1),
+            1,
+            U128(100),
+            accounts(4),
+            SwapOptions::default(),
+            Ok(false),
+        );
+    }
+
+    #[test]
+    fn withdraw_near() {
+        let (mut contract, mut context) = contract();
+        callback(&mut context, PromiseResult::Successful(vec![]));
+        contract.near_deposit_callback(accounts(1), accounts(5), U128(100));
+
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(1)
+            .build());
+        contract.withdraw_near(U128(40));
+        assert_eq!(contract.get_deposit(accounts(1), accounts(5)).0, 60);
+
+        callback(&mut context, PromiseResult::Failed);
+        contract.near_withdraw_callback(accounts(1), accounts(5), U128(40));
+        assert_eq!(contract.get_deposit(accounts(1), accounts(5)).0, 100);
+    }
+
+    #[test]
+    fn swap_and_unwrap() {
+        let (mut contract, mut context) = contract();
+        testing_env!(context.predecessor_account_id(accounts(4)).build());
+        contract.ft_on_transfer(accounts(1), U128(100), "".into());
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        let result = contract.swap(
+            1,
+            accounts(4),
+            U128(100),
+            accounts(5),
+            Some(SwapOptions {
+                unwrap_near: true,
+                ..Default::default()
+            }),
+        );
+        assert_eq!(result.amount_out.0, 90);
+        // the output is on its way to the wNEAR contract
+        assert_eq!(contract.get_deposit(accounts(1), accounts(5)).0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "E059: wNEAR contract is not set")]
+    fn wnear_not_set() {
+        let (mut contract, mut context) = contract();
+        contract.wnear_id = None;
+        testing_env!(context.attached_deposit(100).build());
+        contract.swap_near(1, accounts(4), None, None);
+    }
+}
@@ -0,0 +1,262 @@
+use near_sdk::collections::Vector;
+
+use crate::utils::U256;
+use crate::*;
+
+/// prices are `PRICE_PRECISION` times the amount of the other token per token
+pub const PRICE_PRECISION: u128 = 1_000_000_000_000_000_000;
+/// an observation is recorded at most once per period
+const OBSERVATION_PERIOD_SEC: u64 = 60;
+/// size of the ring buffer, a day of observations
+const OBSERVATION_CAPACITY: u64 = 1440;
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// cumulative prices of the pool tokens at `timestamp`
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Observation {
+    /// seconds
+    pub timestamp: u64,
+    /// sum of the price of each token times the seconds it lasted, wrapping on overflow
+    pub price_cumulative: [u128; 2],
+}
+
+/// price accumulators of a two token pool with a ring buffer of past observations
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct TwapOracle {
+    /// accumulators at the last reserve change
+    last: Observation,
+    observations: Vector<Observation>,
+    /// slot of the next observation once the ring buffer is full
+    next_index: u64,
+}
+
+fn now_sec() -> u64 {
+    env::block_timestamp() / NANOS_PER_SEC
+}
+
+/// price of the token `index` in the other token, zero while the pool is empty
+fn spot_price(amounts: &[Balance], index: usize) -> u128 {
+    let (reserve, reserve_other) = (amounts[index], amounts[1 - index]);
+    if reserve == 0 {
+        return 0;
+    }
+    let price = U256::from(reserve_other) * U256::from(PRICE_PRECISION) / U256::from(reserve);
+    if price > U256::from(u128::MAX) {
+        u128::MAX
+    } else {
+        price.as_u128()
+    }
+}
+
+impl TwapOracle {
+    pub(crate) fn new() -> Self {
+        let mut oracle = Self {
+            last: Observation {
+                timestamp: now_sec(),
+                price_cumulative: [0; 2],
+            },
+            observations: Vector::new(StoreKey::Observations),
+            next_index: 0,
+        };
+        oracle.record(oracle.last);
+        oracle
+    }
+
+    /// the accumulators now, `amounts` are the reserves since the last update
+    pub fn current(&self, amounts: &[Balance]) -> Observation {
+        let now = now_sec();
+        let elapsed = u128::from(now.saturating_sub(self.last.timestamp));
+        let mut observation = self.last;
+        for (index, cumulative) in observation.price_cumulative.iter_mut().enumerate() {
+            *cumulative = cumulative.wrapping_add(spot_price(amounts, index).wrapping_mul(elapsed));
+        }
+        observation.timestamp = std::cmp::max(now, self.last.timestamp);
+        observation
+    }
+
+    /// accumulate the prices of `amounts`, call it before the reserves change
+    pub fn update(&mut self, amounts: &[Balance]) {
+        self.last = self.current(amounts);
+        let latest = self.observation(self.observations.len() - 1);
+        if self.last.timestamp >= latest.timestamp + OBSERVATION_PERIOD_SEC {
+            self.record(self.last);
+        }
+    }
+
+    /// average price of the token `index` in the last `window_seconds`. the accumulator at the
+    /// start of the window is interpolated between the points around it: the observations,
+    /// the last reserve change and now. it is exact when the reserves didn't change between
+    /// the two points and assumes a constant price between them otherwise, e.g. between
+    /// observations which skipped reserve changes less than a period apart.
+    /// a window starting before the oldest observation can't be covered and panics
+    pub fn twap(&self, amounts: &[Balance], index: usize, window_seconds: u64) -> u128 {
+        ensure(window_seconds > 0, DeFiError::InvalidTwapWindow);
+        let current = self.current(amounts);
+        let start = current
+            .timestamp
+            .checked_sub(window_seconds)
+            .and_then(|target| self.cumulative_at(current, index, target))
+            .unwrap_or_else(|| DeFiError::NotEnoughPriceHistory.panic());
+        current.price_cumulative[index].wrapping_sub(start) / u128::from(window_seconds)
+    }
+
+    /// accumulator of the token `index` at `timestamp`, interpolated linearly between the
+    /// latest point at or before it and the next one. `None` before the oldest observation
+    fn cumulative_at(&self, current: Observation, index: usize, timestamp: u64) -> Option<u128> {
+        let (before, after) = if timestamp >= self.last.timestamp {
+            (self.last, current)
+        } else {
+            let before = self.find_at_or_before(timestamp)?;
+            let after = if before + 1 < self.observations.len() {
+                self.observation(before + 1)
+            } else {
+                self.last
+            };
+            (self.observation(before), after)
+        };
+        let cumulative = before.price_cumulative[index];
+        if after.timestamp == before.timestamp {
+            return Some(cumulative);
+        }
+        let delta = after.price_cumulative[index].wrapping_sub(cumulative);
+        let part = U256::from(delta) * U256::from(timestamp - before.timestamp)
+            / U256::from(after.timestamp - before.timestamp);
+        Some(cumulative.wrapping_add(part.as_u128()))
+    }
+
+    /// observation `logical_index` counted from the oldest one
+    fn observation(&self, logical_index: u64) -> Observation {
+        ensure(
+            logical_index < self.observations.len(),
+            DeFiError::ObservationNotFound,
+        );
+        let start = if self.observations.len() < OBSERVATION_CAPACITY {
+            0
+        } else {
+            self.next_index
+        };
+        self.observations
+            .get((start + logical_index) % OBSERVATION_CAPACITY)
+            .unwrap_or_else(|| DeFiError::ObservationNotFound.panic())
+    }
+
+    /// binary search of the index of the latest observation at or before `timestamp`
+    fn find_at_or_before(&self, timestamp: u64) -> Option<u64> {
+        let (mut low, mut high) = (0, self.observations.len());
+        while low < high {
+            let mid = (low + high) / 2;
+            if self.observation(mid).timestamp <= timestamp {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        low.checked_sub(1)
+    }
+
+    fn record(&mut self, observation: Observation) {
+        if self.observations.len() < OBSERVATION_CAPACITY {
+            self.observations.push(&observation);
+        } else {
+            self.observations.replace(self.next_index, &observation);
+        }
+        self.next_index = (self.next_index + 1) % OBSERVATION_CAPACITY;
+    }
+}
+
+#[near_bindgen]
+impl DeFi {
+    /// time weighted average price of `token` in the other token of the TokenA / TokenB pool,
+    /// `PRICE_PRECISION` times the amount of the other token per token
+    pub fn get_twap(&self, token: AccountId, window_seconds: u64) -> U128 {
+        let pool = self.internal_get_pool(0);
+        let index = pool
+            .token_index(&token)
+            .unwrap_or_else(|| DeFiError::UnknownToken.panic());
+        U128(self.oracle.twap(pool.amounts(), index, window_seconds))
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::testing_env;
+
+    use super::*;
+
+    fn set_time(seconds: u64) {
+        testing_env!(VMContextBuilder::new()
+            .block_timestamp(seconds * NANOS_PER_SEC)
+            .build());
+    }
+
+    #[test]
+    fn twap_of_two_prices() {
+        set_time(1000);
+        let mut oracle = TwapOracle::new();
+        // price of token 0 is 2 for 100 seconds, then 4 for 300 seconds
+        set_time(1100);
+        oracle.update(&[100, 200]);
+        set_time(1400);
+        oracle.update(&[100, 400]);
+        assert_eq!(oracle.twap(&[100, 400], 0, 400), 35 * PRICE_PRECISION / 10);
+        assert_eq!(oracle.twap(&[100, 400], 0, 300), 4 * PRICE_PRECISION);
+        assert_eq!(oracle.twap(&[100, 400], 1, 300), PRICE_PRECISION / 4);
+        // the current reserves count until now
+        set_time(1500);
+        assert_eq!(oracle.twap(&[100, 400], 0, 100), 4 * PRICE_PRECISION);
+    }
+
+    #[test]
+    fn ring_buffer_overwrites_oldest() {
+        set_time(0);
+        let mut oracle = TwapOracle::new();
+        for step in 1..=OBSERVATION_CAPACITY + 10 {
+            set_time(step * OBSERVATION_PERIOD_SEC);
+            oracle.update(&[100, 100]);
+        }
+        assert_eq!(oracle.observations.len(), OBSERVATION_CAPACITY);
+        assert_eq!(oracle.observation(0).timestamp, 11 * OBSERVATION_PERIOD_SEC);
+        let window = OBSERVATION_CAPACITY * OBSERVATION_PERIOD_SEC - OBSERVATION_PERIOD_SEC;
+        assert_eq!(oracle.twap(&[100, 100], 0, window), PRICE_PRECISION);
+    }
+
+    #[test]
+    fn twap_interpolated_at_window_start() {
+        set_time(0);
+        let mut oracle = TwapOracle::new();
+        // price of token 0 is 2 until 30, then 4. the update at 30 isn't recorded,
+        // the ones at 60 and 200 are
+        set_time(30);
+        oracle.update(&[100, 200]);
+        set_time(60);
+        oracle.update(&[100, 400]);
+        set_time(200);
+        oracle.update(&[100, 400]);
+        // the window starts at 50, between the observations at 0 and 60, which averaged 3.
+        // the accumulator at 50 is taken as 150 instead of 140
+        set_time(210);
+        assert_eq!(oracle.twap(&[100, 400], 0, 160), 63 * PRICE_PRECISION / 16);
+        // the window starts at 150, the price didn't change between the observations around it
+        assert_eq!(oracle.twap(&[100, 400], 0, 60), 4 * PRICE_PRECISION);
+        // the window starts after the last update
+        assert_eq!(oracle.twap(&[100, 400], 0, 5), 4 * PRICE_PRECISION);
+    }
+
+    #[test]
+    #[should_panic(expected = "E065: price observation not found")]
+    fn observation_past_recorded() {
+        set_time(0);
+        let oracle = TwapOracle::new();
+        oracle.observation(1);
+    }
+
+    #[test]
+    #[should_panic(expected = "E038: not enough price history")]
+    fn window_longer_than_history() {
+        set_time(1000);
+        let oracle = TwapOracle::new();
+        set_time(1100);
+        oracle.twap(&[100, 100], 0, 200);
+    }
+}
@@ -0,0 +1,52 @@
+use crate::events::{OwnerAccepted, OwnerProposed};
+use crate::*;
+
+pub trait Ownable {
+    /// propose `new_owner` as the next owner, it takes effect once `new_owner` accepts it
+    fn propose_owner(&mut self, new_owner: AccountId);
+
+    /// called by the proposed owner to take over the ownership
+    fn accept_ownership(&mut self);
+
+    fn get_owner(&self) -> AccountId;
+
+    fn get_pending_owner(&self) -> Option<AccountId>;
+}
+
+#[near_bindgen]
+impl Ownable for DeFi {
+    fn propose_owner(&mut self, new_owner: AccountId) {
+        self.assert_role(Role::Owner);
+        OwnerProposed {
+            owner_id: &self.owner_id,
+            new_owner_id: &new_owner,
+        }
+        .emit();
+        self.pending_owner_id = Some(new_owner);
+    }
+
+    fn accept_ownership(&mut self) {
+        let new_owner = self
+            .pending_owner_id
+            .take()
+            .unwrap_or_else(|| DeFiError::NoPendingOwner.panic());
+        ensure(
+            env::predecessor_account_id() == new_owner,
+            DeFiError::NotPendingOwner,
+        );
+        OwnerAccepted {
+            old_owner_id: &self.owner_id,
+            new_owner_id: &new_owner,
+        }
+        .emit();
+        self.owner_id = new_owner;
+    }
+
+    fn get_owner(&self) -> AccountId {
+        self.owner_id.clone()
+    }
+
+    fn get_pending_owner(&self) -> Option<AccountId> {
+        self.pending_owner_id.clone()
+    }
+}
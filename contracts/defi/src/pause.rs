@@ -0,0 +1,39 @@
+use crate::events::PauseChanged;
+use crate::*;
+
+/// each flag stops one kind of operation when set
+#[derive(
+    BorshDeserialize, BorshSerialize, Deserialize, Serialize, Default, Clone, Copy, PartialEq, Debug,
+)]
+pub struct PauseFlags {
+    /// `swap_token`, `swap` and filling limit orders
+    pub swap: bool,
+    /// `add_liquidity` and tokens deposited via `ft_transfer_call`
+    pub add_liquidity: bool,
+    /// `remove_liquidity` and `withdraw`
+    pub withdraw: bool,
+}
+
+pub trait Pausable {
+    /// replace the pause flags, only a `Pauser` can call it
+    fn set_paused(&mut self, paused: PauseFlags);
+
+    fn get_paused(&self) -> PauseFlags;
+}
+
+#[near_bindgen]
+impl Pausable for DeFi {
+    fn set_paused(&mut self, paused: PauseFlags) {
+        self.assert_role(Role::Pauser);
+        self.paused = paused;
+        PauseChanged {
+            account_id: &env::predecessor_account_id(),
+            paused: &self.paused,
+        }
+        .emit();
+    }
+
+    fn get_paused(&self) -> PauseFlags {
+        self.paused
+    }
+}
@@ -0,0 +1,314 @@
+use near_sdk::collections::LookupMap;
+
+use crate::concentrated_pool::ConcentratedPool;
+use crate::errors::{check, ensure};
+use crate::simple_pool::{add_reserve, SimplePool};
+use crate::stable_swap::StableSwapPool;
+use crate::utils::U256;
+use crate::weighted_pool::WeightedPool;
+use crate::*;
+
+/// shares minted for the first liquidity of a pool added in proportion
+pub const INIT_SHARES_SUPPLY: Balance = 1_000_000_000_000_000_000_000_000;
+
+/// every kind of pool, they share the swap / quote / liquidity API. a concentrated
+/// liquidity pool has positions instead of shares
+#[derive(BorshSerialize, BorshDeserialize)]
+pub enum Pool {
+    SimplePool(SimplePool),
+    StableSwapPool(StableSwapPool),
+    WeightedPool(WeightedPool),
+    ConcentratedPool(ConcentratedPool),
+}
+
+impl Pool {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Pool::SimplePool(_) => "SIMPLE_POOL",
+            Pool::StableSwapPool(_) => "STABLE_SWAP",
+            Pool::WeightedPool(_) => "WEIGHTED_POOL",
+            Pool::ConcentratedPool(_) => "CONCENTRATED_LIQUIDITY",
+        }
+    }
+
+    pub fn token_account_ids(&self) -> &[AccountId] {
+        match self {
+            Pool::SimplePool(pool) => &pool.token_account_ids,
+            Pool::StableSwapPool(pool) => &pool.token_account_ids,
+            Pool::WeightedPool(pool) => &pool.token_account_ids,
+            Pool::ConcentratedPool(pool) => &pool.token_account_ids,
+        }
+    }
+
+    /// reserves of each token
+    pub fn amounts(&self) -> &[Balance] {
+        match self {
+            Pool::SimplePool(pool) => &pool.amounts,
+            Pool::StableSwapPool(pool) => &pool.amounts,
+            Pool::WeightedPool(pool) => &pool.amounts,
+            Pool::ConcentratedPool(pool) => &pool.amounts,
+        }
+    }
+
+    pub fn total_fee(&self) -> u32 {
+        match self {
+            Pool::SimplePool(pool) => pool.total_fee,
+            Pool::StableSwapPool(pool) => pool.total_fee,
+            Pool::WeightedPool(pool) => pool.total_fee,
+            Pool::ConcentratedPool(pool) => pool.total_fee,
+        }
+    }
+
+    pub fn set_total_fee(&mut self, total_fee: u32) {
+        match self {
+            Pool::SimplePool(pool) => pool.total_fee = total_fee,
+            Pool::StableSwapPool(pool) => pool.total_fee = total_fee,
+            Pool::WeightedPool(pool) => pool.total_fee = total_fee,
+            Pool::ConcentratedPool(pool) => pool.total_fee = total_fee,
+        }
+    }
+
+    pub fn shares_total_supply(&self) -> Balance {
+        match self {
+            Pool::SimplePool(pool) => pool.shares.total_supply,
+            Pool::StableSwapPool(pool) => pool.shares.total_supply,
+            Pool::WeightedPool(pool) => pool.shares.total_supply,
+            Pool::ConcentratedPool(pool) => pool.total_liquidity,
+        }
+    }
+
+    pub fn token_index(&self, token_id: &AccountId) -> Option<usize> {
+        token_index(self.token_account_ids(), token_id)
+    }
+
+    pub fn share_balance_of(&self, account_id: &AccountId) -> Balance {
+        match self {
+            Pool::SimplePool(pool) => pool.shares.balance_of(account_id),
+            Pool::StableSwapPool(pool) => pool.shares.balance_of(account_id),
+            Pool::WeightedPool(pool) => pool.shares.balance_of(account_id),
+            Pool::ConcentratedPool(pool) => pool.share_balance_of(account_id),
+        }
+    }
+
+    /// add liquidity, `amounts` is updated to the amounts used.
+    /// return the shares minted to `account_id`
+    pub fn add_liquidity(&mut self, account_id: &AccountId, amounts: &mut [Balance]) -> Balance {
+        match self {
+            Pool::SimplePool(pool) => pool.add_liquidity(account_id, amounts),
+            Pool::StableSwapPool(pool) => pool.add_liquidity(account_id, amounts),
+            Pool::WeightedPool(pool) => pool.add_liquidity(account_id, amounts),
+            Pool::ConcentratedPool(_) => DeFiError::SharesNotSupported.panic(),
+        }
+    }
+
+    pub fn remove_liquidity(
+        &mut self,
+        account_id: &AccountId,
+        shares: Balance,
+        min_amounts: &[Balance],
+    ) -> Vec<Balance> {
+        match self {
+            Pool::SimplePool(pool) => pool.remove_liquidity(account_id, shares, min_amounts),
+            Pool::StableSwapPool(pool) => pool.remove_liquidity(account_id, shares, min_amounts),
+            Pool::WeightedPool(pool) => pool.remove_liquidity(account_id, shares, min_amounts),
+            Pool::ConcentratedPool(_) => DeFiError::SharesNotSupported.panic(),
+        }
+    }
+
+    pub fn get_return(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+    ) -> Balance {
+        match self {
+            Pool::SimplePool(pool) => pool.get_return(token_in, amount_in, token_out),
+            Pool::StableSwapPool(pool) => pool.get_return(token_in, amount_in, token_out),
+            Pool::WeightedPool(pool) => pool.get_return(token_in, amount_in, token_out),
+            Pool::ConcentratedPool(pool) => pool.get_return(token_in, amount_in, token_out),
+        }
+    }
+
+    /// the amount of `token_out` `swap` would return, or the error it would panic with
+    pub fn try_swap_return(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+    ) -> Result<Balance, DeFiError> {
+        check(amount_in > 0, DeFiError::ZeroAmount)?;
+        check(token_in != token_out, DeFiError::SameToken)?;
+        let amount_out = match self {
+            Pool::SimplePool(pool) => pool.try_get_return(token_in, amount_in, token_out),
+            Pool::StableSwapPool(pool) => pool.try_get_return(token_in, amount_in, token_out),
+            Pool::WeightedPool(pool) => pool.try_get_return(token_in, amount_in, token_out),
+            Pool::ConcentratedPool(pool) => pool.try_get_return(token_in, amount_in, token_out),
+        }?;
+        check(amount_out > 0, DeFiError::ZeroAmount)?;
+        Ok(amount_out)
+    }
+
+    pub fn swap(
+        &mut self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+    ) -> Balance {
+        match self {
+            Pool::SimplePool(pool) => pool.swap(token_in, amount_in, token_out),
+            Pool::StableSwapPool(pool) => pool.swap(token_in, amount_in, token_out),
+            Pool::WeightedPool(pool) => pool.swap(token_in, amount_in, token_out),
+            Pool::ConcentratedPool(pool) => pool.swap(token_in, amount_in, token_out),
+        }
+    }
+
+    /// move of the price of `token_in` in `token_out` caused by swapping `amount_in`,
+    /// in basis points
+    pub fn price_impact(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+    ) -> u32 {
+        match self {
+            Pool::SimplePool(pool) => pool.price_impact(token_in, amount_in, token_out),
+            Pool::StableSwapPool(pool) => pool.price_impact(token_in, amount_in, token_out),
+            Pool::WeightedPool(pool) => pool.price_impact(token_in, amount_in, token_out),
+            Pool::ConcentratedPool(pool) => pool.price_impact(token_in, amount_in, token_out),
+        }
+    }
+
+    pub fn fee_of(&self, amount_in: Balance) -> Balance {
+        fee_of(self.total_fee(), amount_in)
+    }
+
+    /// reserves which keep the swap fees, a concentrated liquidity pool
+    /// shares the fees with its positions instead
+    pub fn fee_amounts_mut(&mut self) -> Option<&mut [Balance]> {
+        match self {
+            Pool::SimplePool(pool) => Some(&mut pool.amounts),
+            Pool::StableSwapPool(pool) => Some(&mut pool.amounts),
+            Pool::WeightedPool(pool) => Some(&mut pool.amounts),
+            Pool::ConcentratedPool(_) => None,
+        }
+    }
+
+    /// reserves lent and paid back by flash swaps, a concentrated liquidity pool prices
+    /// with its liquidity instead of its reserves and can't lend them
+    pub fn flash_amounts_mut(&mut self) -> &mut [Balance] {
+        match self {
+            Pool::SimplePool(pool) => &mut pool.amounts,
+            Pool::StableSwapPool(pool) => &mut pool.amounts,
+            Pool::WeightedPool(pool) => &mut pool.amounts,
+            Pool::ConcentratedPool(_) => DeFiError::FlashSwapNotSupported.panic(),
+        }
+    }
+}
+
+/// index of `token_id` in the tokens of a pool
+pub(crate) fn token_index(token_account_ids: &[AccountId], token_id: &AccountId) -> Option<usize> {
+    token_account_ids.iter().position(|id| id == token_id)
+}
+
+pub(crate) fn assert_token_index(token_account_ids: &[AccountId], token_id: &AccountId) -> usize {
+    token_index(token_account_ids, token_id).unwrap_or_else(|| DeFiError::UnknownToken.panic())
+}
+
+/// swap fee of `total_fee` basis points charged on `amount_in`
+pub(crate) fn fee_of(total_fee: u32, amount_in: Balance) -> Balance {
+    (U256::from(amount_in) * U256::from(total_fee) / U256::from(FEE_DIVISOR)).as_u128()
+}
+
+/// LP shares of a pool which issues shares. it takes the place of the `shares_total_supply`
+/// and `shares` fields of the pools and is stored the same way
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct PoolShares {
+    /// LP shares issued for the pool
+    pub total_supply: Balance,
+    /// (account, LP shares) map
+    accounts: LookupMap<AccountId, Balance>,
+}
+
+impl PoolShares {
+    pub fn new(pool_id: u64) -> Self {
+        Self {
+            total_supply: 0,
+            accounts: LookupMap::new(StoreKey::Shares { pool_id }),
+        }
+    }
+
+    pub fn balance_of(&self, account_id: &AccountId) -> Balance {
+        self.accounts.get(account_id).unwrap_or(0)
+    }
+
+    pub fn mint(&mut self, account_id: &AccountId, shares: Balance) {
+        self.accounts
+            .insert(account_id, &(self.balance_of(account_id) + shares));
+        self.total_supply += shares;
+    }
+
+    /// add `amounts` to `reserves` in proportion to them, `amounts` is updated to the amounts
+    /// used. the first liquidity sets the proportion. return the shares to mint
+    pub fn add_in_proportion(&self, reserves: &mut [Balance], amounts: &mut [Balance]) -> Balance {
+        ensure(amounts.len() == reserves.len(), DeFiError::InvalidAmounts);
+        if self.total_supply == 0 {
+            for (amount, reserve) in amounts.iter().zip(reserves.iter_mut()) {
+                ensure(*amount > 0, DeFiError::ZeroAmount);
+                *reserve = add_reserve(*reserve, *amount);
+            }
+            return INIT_SHARES_SUPPLY;
+        }
+        let total = U256::from(self.total_supply);
+        let fair_supply = amounts
+            .iter()
+            .zip(reserves.iter())
+            .map(|(amount, reserve)| U256::from(*amount) * total / U256::from(*reserve))
+            .min()
+            .unwrap_or_default();
+        for (amount, reserve) in amounts.iter_mut().zip(reserves.iter_mut()) {
+            let used = (U256::from(*reserve) * fair_supply / total).as_u128();
+            ensure(used > 0, DeFiError::ZeroAmount);
+            *reserve = add_reserve(*reserve, used);
+            *amount = used;
+        }
+        fair_supply.as_u128()
+    }
+
+    /// burn `shares` of `account_id` and take their part of `reserves`.
+    /// return the token amounts of them
+    pub fn burn(
+        &mut self,
+        account_id: &AccountId,
+        shares: Balance,
+        reserves: &mut [Balance],
+        min_amounts: &[Balance],
+    ) -> Vec<Balance> {
+        ensure(
+            min_amounts.len() == reserves.len(),
+            DeFiError::InvalidAmounts,
+        );
+        let prev_shares = self.balance_of(account_id);
+        ensure(
+            shares > 0 && prev_shares >= shares,
+            DeFiError::NotEnoughShares,
+        );
+        let total = U256::from(self.total_supply);
+        let amounts: Vec<Balance> = reserves
+            .iter_mut()
+            .zip(min_amounts.iter())
+            .map(|(reserve, min_amount)| {
+                let amount = (U256::from(*reserve) * U256::from(shares) / total).as_u128();
+                ensure(amount >= *min_amount, DeFiError::MinAmountNotReached);
+                *reserve -= amount;
+                amount
+            })
+            .collect();
+        if prev_shares == shares {
+            self.accounts.remove(account_id);
+        } else {
+            self.accounts.insert(account_id, &(prev_shares - shares));
+        }
+        self.total_supply -= shares;
+        amounts
+    }
+}
@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+
+use crate::events::{ReferralFeeChanged, ReferralFeePaid, ReferralFeesClaimed};
+use crate::*;
+
+/// fees earned by a referrer, in the tokens swapped in
+#[derive(BorshSerialize, BorshDeserialize, Default)]
+pub struct Referral {
+    /// fees not claimed yet
+    pub unclaimed: HashMap<AccountId, Balance>,
+    /// every fee earned
+    pub earned: HashMap<AccountId, Balance>,
+    /// swaps which paid a fee to the referrer
+    pub swaps: u64,
+}
+
+/// view of the fees of a referrer
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ReferralStats {
+    pub unclaimed: HashMap<AccountId, U128>,
+    pub earned: HashMap<AccountId, U128>,
+    pub swaps: U64,
+}
+
+impl From<Referral> for ReferralStats {
+    fn from(referral: Referral) -> Self {
+        let to_json = |fees: HashMap<AccountId, Balance>| {
+            fees.into_iter()
+                .map(|(token_id, amount)| (token_id, U128(amount)))
+                .collect()
+        };
+        Self {
+            unclaimed: to_json(referral.unclaimed),
+            earned: to_json(referral.earned),
+            swaps: U64(referral.swaps),
+        }
+    }
+}
+
+#[near_bindgen]
+impl DeFi {
+    /// set the share of the swap fee paid to referrers, in basis points of the fee
+    pub fn set_referral_fee(&mut self, referral_fee: u32) {
+        self.assert_role(Role::FeeManager);
+        ensure(referral_fee <= FEE_DIVISOR, DeFiError::FeeTooLarge);
+        ReferralFeeChanged {
+            account_id: &env::predecessor_account_id(),
+            old_fee: self.referral_fee,
+            new_fee: referral_fee,
+        }
+        .emit();
+        self.referral_fee = referral_fee;
+    }
+
+    pub fn get_referral_fee(&self) -> u32 {
+        self.referral_fee
+    }
+
+    /// move the unclaimed referral fees of the caller to its internal balances
+    pub fn claim_referral_fees(&mut self) -> HashMap<AccountId, U128> {
+        let account_id = env::predecessor_account_id();
+        let mut referral = self.referrals.get(&account_id).unwrap_or_default();
+        let unclaimed = std::mem::take(&mut referral.unclaimed);
+        let prev_storage = env::storage_usage();
+        self.referrals.insert(&account_id, &referral);
+        for (token_id, amount) in unclaimed.iter() {
+            self.internal_deposit(&account_id, token_id, *amount);
+        }
+        self.internal_update_storage(&account_id, prev_storage);
+        let claimed: HashMap<AccountId, U128> = unclaimed
+            .into_iter()
+            .map(|(token_id, amount)| (token_id, U128(amount)))
+            .collect();
+        ReferralFeesClaimed {
+            account_id: &account_id,
+            fees: &claimed,
+        }
+        .emit();
+        claimed
+    }
+
+    pub fn get_referral_stats(&self, account_id: AccountId) -> ReferralStats {
+        self.referrals.get(&account_id).unwrap_or_default().into()
+    }
+}
+
+impl DeFi {
+    pub(crate) fn assert_referral(&self, account_id: &AccountId, referral_id: Option<&AccountId>) {
+        ensure(referral_id != Some(account_id), DeFiError::SelfReferral);
+    }
+
+    /// move the referral share of the fee of the swap `result` out of the fee kept by `pool`
+    /// to `referral_id`, before the pool is saved. the pool keeps the whole fee when the
+    /// referrer isn't registered or its storage deposit can't pay for the fee, and
+    /// concentrated liquidity pools keep the fees with their positions
+    pub(crate) fn internal_pay_referral_fee(
+        &mut self,
+        referral_id: Option<&AccountId>,
+        pool: &mut Pool,
+        result: &SwapResult,
+    ) {
+        let referral_id = match referral_id {
+            Some(referral_id) if result.status == SwapStatus::Success => referral_id,
+            _ => return,
+        };
+        let amount = (U256::from(result.fee.0) * U256::from(self.referral_fee)
+            / U256::from(FEE_DIVISOR))
+        .as_u128();
+        let token_index = match pool.token_index(&result.token_in) {
+            Some(token_index) => token_index,
+            None => return,
+        };
+        let fee_amounts = match pool.fee_amounts_mut() {
+            Some(fee_amounts) => fee_amounts,
+            None => return,
+        };
+        if amount == 0 || !self.internal_credit_referral(referral_id, &result.token_in, amount) {
+            return;
+        }
+        fee_amounts[token_index] -= amount;
+        ReferralFeePaid {
+            swap_id: &result.swap_id,
+            referral_id,
+            token_id: &result.token_in,
+            amount: &U128(amount),
+        }
+        .emit();
+    }
+
+    /// add `amount` of `token_id` to the referral fees of `referral_id`. return false and
+    /// leave them unchanged if it isn't registered or its storage deposit can't pay for them
+    fn internal_credit_referral(
+        &mut self,
+        referral_id: &AccountId,
+        token_id: &AccountId,
+        amount: Balance,
+    ) -> bool {
+        if !self.accounts.contains_key(referral_id) {
+            return false;
+        }
+        let prev_storage = env::storage_usage();
+        let prev_referral = self.referrals.get(referral_id);
+        let mut referral = self.referrals.get(referral_id).unwrap_or_default();
+        for fees in [&mut referral.unclaimed, &mut referral.earned] {
+            let fee = fees.entry(token_id.clone()).or_default();
+            *fee = fee.saturating_add(amount);
+        }
+        referral.swaps += 1;
+        self.referrals.insert(referral_id, &referral);
+        let storage = env::storage_usage();
+        let account = self.internal_track_storage(referral_id, prev_storage);
+        if account.storage_needed() <= account.near_amount {
+            return true;
+        }
+        log!("referrer {} has not enough storage deposit", referral_id);
+        match prev_referral {
+            Some(prev_referral) => self.referrals.insert(referral_id, &prev_referral),
+            None => self.referrals.remove(referral_id),
+        };
+        self.internal_track_storage(referral_id, storage);
+        false
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::test_utils::{deposit, pool_contract, register};
+
+    /// pool 0 with 1_000_000 TokenA and 1_000_000 TokenB and a 30 basis points fee,
+    /// `accounts(1)` holds 100_000 TokenA and half of the fee goes to referrers
+    fn contract() -> (DeFi, VMContextBuilder) {
+        let (mut contract, mut context) = pool_contract(1_000_000);
+        deposit(
+            &mut contract,
+            &mut context,
+            accounts(1),
+            accounts(3),
+            100_000,
+        );
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.set_referral_fee(5000);
+        (contract, context)
+    }
+
+    #[test]
+    fn referral_fee_paid_and_claimed() {
+        let (mut contract, mut context) = contract();
+        register(&mut contract, &mut context, accounts(2));
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        let result = contract.swap(
+            0,
+            accounts(3),
+            U128(100_000),
+            accounts(4),
+            Some(SwapOptions {
+                referral_id: Some(accounts(2)),
+                ..Default::default()
+            }),
+        );
+        assert_eq!(result.fee.0, 300);
+        assert_eq!(contract.get_pool_info().reserve_a.0, 1_100_000 - 150);
+        let stats = contract.get_referral_stats(accounts(2));
+        assert_eq!(stats.unclaimed[&accounts(3)].0, 150);
+        assert_eq!(stats.earned[&accounts(3)].0, 150);
+        assert_eq!(stats.swaps.0, 1);
+
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        let claimed = contract.claim_referral_fees();
+        assert_eq!(claimed[&accounts(3)].0, 150);
+        assert_eq!(contract.get_deposit(accounts(2), accounts(3)).0, 150);
+        let stats = contract.get_referral_stats(accounts(2));
+        assert!(stats.unclaimed.is_empty());
+        assert_eq!(stats.earned[&accounts(3)].0, 150);
+    }
+
+    #[test]
+    fn unregistered_referrer() {
+        let (mut contract, mut context) = contract();
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.swap(
+            0,
+            accounts(3),
+            U128(100_000),
+            accounts(4),
+            Some(SwapOptions {
+                referral_id: Some(accounts(2)),
+                ..Default::default()
+            }),
+        );
+        assert_eq!(contract.get_pool_info().reserve_a.0, 1_100_000);
+        assert_eq!(contract.get_referral_stats(accounts(2)).swaps.0, 0);
+    }
+
+    #[test]
+    fn referrer_without_storage() {
+        let (mut contract, mut context) = contract();
+        register(&mut contract, &mut context, accounts(2));
+        testing_env!(context.attached_deposit(1).build());
+        contract.storage_withdraw(None);
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(0)
+            .build());
+        contract.swap(
+            0,
+            accounts(3),
+            U128(100_000),
+            accounts(4),
+            Some(SwapOptions {
+                referral_id: Some(accounts(2)),
+                ..Default::default()
+            }),
+        );
+        // the pool keeps the whole fee
+        assert_eq!(contract.get_pool_info().reserve_a.0, 1_100_000);
+        assert_eq!(contract.get_referral_stats(accounts(2)).swaps.0, 0);
+        assert_eq!(
+            contract
+                .storage_balance_of(accounts(2))
+                .unwrap()
+                .available
+                .0,
+            0
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "E064: can't refer own swaps")]
+    fn self_referral() {
+        let (mut contract, mut context) = contract();
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.swap(
+            0,
+            accounts(3),
+            U128(100_000),
+            accounts(4),
+            Some(SwapOptions {
+                referral_id: Some(accounts(1)),
+                ..Default::default()
+            }),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "E009: fee is too large")]
+    fn referral_fee_too_large() {
+        let (mut contract, _) = contract();
+        contract.set_referral_fee(FEE_DIVISOR + 1);
+    }
+}
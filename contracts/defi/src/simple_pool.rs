@@ -0,0 +1,178 @@
+use near_sdk::{AccountId, Balance};
+
+use crate::errors::{check, ensure};
+use crate::pool::{assert_token_index, token_index, PoolShares};
+use crate::utils::{price_move, reserve_price, reserves_after, U256};
+use crate::*;
+
+pub(crate) fn add_reserve(reserve: Balance, amount: Balance) -> Balance {
+    reserve
+        .checked_add(amount)
+        .unwrap_or_else(|| DeFiError::ReserveOverflow.panic())
+}
+
+/// constant product pool, x * y = k
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct SimplePool {
+    /// token contracts of the pool, the index of a token is used by `amounts`
+    pub token_account_ids: Vec<AccountId>,
+    /// reserves of each token
+    pub amounts: Vec<Balance>,
+    /// swap fee in basis points
+    pub total_fee: u32,
+    pub shares: PoolShares,
+}
+
+impl SimplePool {
+    pub fn new(pool_id: u64, token_account_ids: Vec<AccountId>, total_fee: u32) -> Self {
+        ensure(total_fee < FEE_DIVISOR, DeFiError::FeeTooLarge);
+        Self {
+            amounts: vec![0; token_account_ids.len()],
+            token_account_ids,
+            total_fee,
+            shares: PoolShares::new(pool_id),
+        }
+    }
+
+    /// add liquidity in proportion to the reserves, `amounts` is updated to the amounts used.
+    /// return the shares minted to `account_id`
+    pub fn add_liquidity(&mut self, account_id: &AccountId, amounts: &mut [Balance]) -> Balance {
+        let shares = self.shares.add_in_proportion(&mut self.amounts, amounts);
+        self.shares.mint(account_id, shares);
+        shares
+    }
+
+    /// burn `shares` of `account_id` and return the token amounts of them
+    pub fn remove_liquidity(
+        &mut self,
+        account_id: &AccountId,
+        shares: Balance,
+        min_amounts: &[Balance],
+    ) -> Vec<Balance> {
+        self.shares
+            .burn(account_id, shares, &mut self.amounts, min_amounts)
+    }
+
+    /// how many `token_out` would be returned for `amount_in` of `token_in`
+    pub fn get_return(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+    ) -> Balance {
+        self.try_get_return(token_in, amount_in, token_out)
+            .unwrap_or_else(|error| error.panic())
+    }
+
+    /// like `get_return` but return the error instead of panicking
+    pub fn try_get_return(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+    ) -> Result<Balance, DeFiError> {
+        let in_idx =
+            token_index(&self.token_account_ids, token_in).ok_or(DeFiError::UnknownToken)?;
+        let out_idx =
+            token_index(&self.token_account_ids, token_out).ok_or(DeFiError::UnknownToken)?;
+        let in_balance = U256::from(self.amounts[in_idx]);
+        let out_balance = U256::from(self.amounts[out_idx]);
+        check(
+            !in_balance.is_zero() && !out_balance.is_zero(),
+            DeFiError::EmptyPool,
+        )?;
+        let amount_with_fee = U256::from(amount_in) * U256::from(FEE_DIVISOR - self.total_fee);
+        Ok((amount_with_fee * out_balance
+            / (U256::from(FEE_DIVISOR) * in_balance + amount_with_fee))
+            .as_u128())
+    }
+
+    /// swap `amount_in` of `token_in` into the pool, return the amount of `token_out`
+    pub fn swap(
+        &mut self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+    ) -> Balance {
+        ensure(amount_in > 0, DeFiError::ZeroAmount);
+        let amount_out = self.get_return(token_in, amount_in, token_out);
+        ensure(amount_out > 0, DeFiError::ZeroAmount);
+        let in_idx = assert_token_index(&self.token_account_ids, token_in);
+        let out_idx = assert_token_index(&self.token_account_ids, token_out);
+        self.amounts[in_idx] = add_reserve(self.amounts[in_idx], amount_in);
+        self.amounts[out_idx] -= amount_out;
+        amount_out
+    }
+
+    /// move of the price of `token_in` in `token_out` caused by swapping `amount_in`,
+    /// in basis points
+    pub fn price_impact(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+    ) -> u32 {
+        let amount_out = self.get_return(token_in, amount_in, token_out);
+        let in_balance = self.amounts[assert_token_index(&self.token_account_ids, token_in)];
+        let out_balance = self.amounts[assert_token_index(&self.token_account_ids, token_out)];
+        let (in_after, out_after) = reserves_after(in_balance, amount_in, out_balance, amount_out);
+        price_move(
+            reserve_price(U256::from(in_balance), U256::from(out_balance)),
+            reserve_price(in_after, out_after),
+        )
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::accounts;
+
+    use super::*;
+    use crate::pool::{fee_of, INIT_SHARES_SUPPLY};
+
+    #[test]
+    fn add_swap_remove() {
+        let mut pool = SimplePool::new(0, vec![accounts(3), accounts(4)], 0);
+        let shares = pool.add_liquidity(&accounts(0), &mut [1000, 1000]);
+        assert_eq!(shares, INIT_SHARES_SUPPLY);
+
+        let amount_out = pool.swap(&accounts(3), 100, &accounts(4));
+        assert_eq!(amount_out, 90);
+        assert_eq!(pool.amounts, vec![1100, 910]);
+
+        // only the proportional part is used
+        let mut amounts = [110, 1000];
+        let shares = pool.add_liquidity(&accounts(1), &mut amounts);
+        assert_eq!(shares, INIT_SHARES_SUPPLY / 10);
+        assert_eq!(amounts, [110, 91]);
+
+        let amounts = pool.remove_liquidity(&accounts(1), shares, &[0, 0]);
+        assert_eq!(amounts, vec![110, 91]);
+        assert_eq!(pool.shares.balance_of(&accounts(1)), 0);
+        assert_eq!(pool.shares.total_supply, INIT_SHARES_SUPPLY);
+    }
+
+    #[test]
+    fn swap_with_fee() {
+        let mut pool = SimplePool::new(0, vec![accounts(3), accounts(4)], 1000);
+        pool.add_liquidity(&accounts(0), &mut [1000, 1000]);
+        assert_eq!(pool.get_return(&accounts(3), 100, &accounts(4)), 82);
+        assert_eq!(fee_of(pool.total_fee, 100), 10);
+    }
+
+    #[test]
+    fn price_impact() {
+        let mut pool = SimplePool::new(0, vec![accounts(3), accounts(4)], 0);
+        pool.add_liquidity(&accounts(0), &mut [1000, 1000]);
+        // the price of token 3 goes from 1 to 910 / 1100
+        assert_eq!(pool.price_impact(&accounts(3), 100, &accounts(4)), 1727);
+    }
+
+    #[test]
+    #[should_panic(expected = "E025: not enough shares")]
+    fn remove_too_many_shares() {
+        let mut pool = SimplePool::new(0, vec![accounts(3), accounts(4)], 0);
+        pool.add_liquidity(&accounts(0), &mut [1000, 1000]);
+        pool.remove_liquidity(&accounts(0), INIT_SHARES_SUPPLY + 1, &[0, 0]);
+    }
+}
@@ -0,0 +1,478 @@
+use near_sdk::{AccountId, Balance, Timestamp};
+
+use crate::errors::{check, ensure};
+use crate::pool::{assert_token_index, fee_of, token_index, PoolShares};
+use crate::simple_pool::add_reserve;
+use crate::utils::{price_move, U256};
+use crate::*;
+
+pub const MIN_AMP: u128 = 1;
+pub const MAX_AMP: u128 = 1_000_000;
+/// a ramp can change the amp factor at most by this factor
+const MAX_AMP_CHANGE: u128 = 10;
+/// a ramp lasts at least a day
+const MIN_RAMP_DURATION: Timestamp = 86_400 * 1_000_000_000;
+/// amounts are scaled to this many decimals for the invariant
+const TARGET_DECIMALS: u8 = 18;
+pub const MAX_STABLE_TOKENS: usize = 4;
+/// iterations of the Newton's method before giving up
+const MAX_ITERATIONS: usize = 256;
+
+/// StableSwap invariant `A * n^n * sum(x) + D = A * D * n^n + D^(n+1) / (n^n * prod(x))`
+/// of the `xp` amounts, `ann` is `A * n^n`
+fn compute_d(xp: &[U256], ann: U256) -> U256 {
+    let n = U256::from(xp.len());
+    let sum = xp.iter().fold(U256::zero(), |sum, x| sum + x);
+    if sum.is_zero() {
+        return sum;
+    }
+    let mut d = sum;
+    for _ in 0..MAX_ITERATIONS {
+        let d_p = xp.iter().fold(d, |d_p, x| d_p * d / (*x * n));
+        let d_prev = d;
+        d = (ann * sum + d_p * n) * d / ((ann - 1) * d + (n + 1) * d_p);
+        if abs_diff(d, d_prev) <= U256::one() {
+            return d;
+        }
+    }
+    DeFiError::InvariantNotConverged.panic()
+}
+
+/// amount of the token `j` which keeps `d` when the token `i` is `x`
+fn compute_y(xp: &[U256], i: usize, j: usize, x: U256, d: U256, ann: U256) -> U256 {
+    let n = U256::from(xp.len());
+    let mut c = d;
+    let mut sum = U256::zero();
+    for (k, xp_k) in xp.iter().enumerate() {
+        if k == j {
+            continue;
+        }
+        let x_k = if k == i { x } else { *xp_k };
+        sum += x_k;
+        c = c * d / (x_k * n);
+    }
+    c = c * d / (ann * n);
+    let b = sum + d / ann;
+    let mut y = d;
+    for _ in 0..MAX_ITERATIONS {
+        let y_prev = y;
+        y = (y * y + c) / (y * 2 + b - d);
+        if abs_diff(y, y_prev) <= U256::one() {
+            return y;
+        }
+    }
+    DeFiError::InvariantNotConverged.panic()
+}
+
+/// marginal price of the token `i` in the token `j` of the normalized reserves `xp`,
+/// probed with a millionth of the reserve of `i`
+fn marginal_price(xp: &[U256], i: usize, j: usize, ann: U256) -> U256 {
+    let d = compute_d(xp, ann);
+    let dx = xp[i] / U256::from(1_000_000) + U256::one();
+    let y = compute_y(xp, i, j, xp[i] + dx, d, ann);
+    xp[j].saturating_sub(y) * U256::from(PRICE_PRECISION) / dx
+}
+
+fn abs_diff(a: U256, b: U256) -> U256 {
+    if a > b {
+        a - b
+    } else {
+        b - a
+    }
+}
+
+/// StableSwap pool for correlated tokens, prices stay near 1:1 until the reserves are
+/// unbalanced. the amp factor can be ramped linearly by the owner
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct StableSwapPool {
+    /// token contracts of the pool, the index of a token is used by `amounts`
+    pub token_account_ids: Vec<AccountId>,
+    pub token_decimals: Vec<u8>,
+    /// reserves of each token, in the decimals of the token
+    pub amounts: Vec<Balance>,
+    /// swap fee in basis points
+    pub total_fee: u32,
+    pub shares: PoolShares,
+    init_amp_factor: u128,
+    target_amp_factor: u128,
+    init_amp_time: Timestamp,
+    stop_amp_time: Timestamp,
+}
+
+impl StableSwapPool {
+    pub fn new(
+        pool_id: u64,
+        token_account_ids: Vec<AccountId>,
+        token_decimals: Vec<u8>,
+        total_fee: u32,
+        amp_factor: u128,
+    ) -> Self {
+        ensure(total_fee < FEE_DIVISOR, DeFiError::FeeTooLarge);
+        ensure(
+            token_account_ids.len() == token_decimals.len()
+                && token_decimals.iter().all(|d| *d <= TARGET_DECIMALS),
+            DeFiError::InvalidPoolTokens,
+        );
+        ensure(
+            (MIN_AMP..=MAX_AMP).contains(&amp_factor),
+            DeFiError::InvalidAmp,
+        );
+        Self {
+            amounts: vec![0; token_account_ids.len()],
+            token_account_ids,
+            token_decimals,
+            total_fee,
+            shares: PoolShares::new(pool_id),
+            init_amp_factor: amp_factor,
+            target_amp_factor: amp_factor,
+            init_amp_time: 0,
+            stop_amp_time: 0,
+        }
+    }
+
+    /// the amp factor now, moving linearly during a ramp
+    pub fn amp_factor(&self) -> u128 {
+        let now = env::block_timestamp();
+        if now >= self.stop_amp_time {
+            return self.target_amp_factor;
+        }
+        let elapsed = U256::from(now - self.init_amp_time);
+        let duration = U256::from(self.stop_amp_time - self.init_amp_time);
+        if self.target_amp_factor > self.init_amp_factor {
+            let change = U256::from(self.target_amp_factor - self.init_amp_factor);
+            self.init_amp_factor + (change * elapsed / duration).as_u128()
+        } else {
+            let change = U256::from(self.init_amp_factor - self.target_amp_factor);
+            self.init_amp_factor - (change * elapsed / duration).as_u128()
+        }
+    }
+
+    /// move the amp factor linearly to `future_amp_factor` until `future_amp_time`
+    pub fn ramp_amp(&mut self, future_amp_factor: u128, future_amp_time: Timestamp) {
+        ensure(
+            (MIN_AMP..=MAX_AMP).contains(&future_amp_factor),
+            DeFiError::InvalidAmp,
+        );
+        let now = env::block_timestamp();
+        let amp_factor = self.amp_factor();
+        ensure(
+            future_amp_time >= now.saturating_add(MIN_RAMP_DURATION)
+                && future_amp_factor <= amp_factor * MAX_AMP_CHANGE
+                && future_amp_factor * MAX_AMP_CHANGE >= amp_factor,
+            DeFiError::InvalidRamp,
+        );
+        self.init_amp_factor = amp_factor;
+        self.init_amp_time = now;
+        self.target_amp_factor = future_amp_factor;
+        self.stop_amp_time = future_amp_time;
+    }
+
+    /// keep the current amp factor
+    pub fn stop_ramp_amp(&mut self) {
+        let amp_factor = self.amp_factor();
+        let now = env::block_timestamp();
+        self.init_amp_factor = amp_factor;
+        self.target_amp_factor = amp_factor;
+        self.init_amp_time = now;
+        self.stop_amp_time = now;
+    }
+
+    fn ann(&self) -> U256 {
+        let n = self.amounts.len() as u32;
+        U256::from(self.amp_factor()) * U256::from(n).pow(U256::from(n))
+    }
+
+    fn rate(&self, index: usize) -> U256 {
+        U256::from(10u128.pow(u32::from(TARGET_DECIMALS - self.token_decimals[index])))
+    }
+
+    /// `amounts` in 18 decimals
+    fn normalize(&self, amounts: &[Balance]) -> Vec<U256> {
+        amounts
+            .iter()
+            .enumerate()
+            .map(|(index, amount)| U256::from(*amount) * self.rate(index))
+            .collect()
+    }
+
+    /// add liquidity in any proportion, all of `amounts` is used. the part which unbalances
+    /// the pool pays the swap fee. return the shares minted to `account_id`
+    pub fn add_liquidity(&mut self, account_id: &AccountId, amounts: &mut [Balance]) -> Balance {
+        ensure(
+            amounts.len() == self.token_account_ids.len(),
+            DeFiError::InvalidAmounts,
+        );
+        // the invariant of the first liquidity divides by each amount
+        if self.shares.total_supply == 0 {
+            ensure(amounts.iter().all(|a| *a > 0), DeFiError::ZeroAmount);
+        }
+        let ann = self.ann();
+        let new_amounts: Vec<Balance> = self
+            .amounts
+            .iter()
+            .zip(amounts.iter())
+            .map(|(reserve, amount)| add_reserve(*reserve, *amount))
+            .collect();
+        let xp_old = self.normalize(&self.amounts);
+        let xp_new = self.normalize(&new_amounts);
+        let d1 = compute_d(&xp_new, ann);
+        let shares = if self.shares.total_supply == 0 {
+            d1
+        } else {
+            let d0 = compute_d(&xp_old, ann);
+            ensure(d1 > d0, DeFiError::ZeroAmount);
+            // Curve's imbalance fee, the fee of a swap moving the same amounts
+            let n = xp_old.len() as u32;
+            let fee = U256::from(self.total_fee * n) / U256::from(4 * (n - 1));
+            let xp_fee: Vec<U256> = xp_old
+                .iter()
+                .zip(xp_new.iter())
+                .map(|(old, new)| {
+                    let ideal = d1 * *old / d0;
+                    *new - fee * abs_diff(ideal, *new) / U256::from(FEE_DIVISOR)
+                })
+                .collect();
+            let d2 = compute_d(&xp_fee, ann);
+            U256::from(self.shares.total_supply) * (d2 - d0) / d0
+        };
+        ensure(shares > U256::zero(), DeFiError::ZeroAmount);
+        let shares = shares.as_u128();
+        self.amounts = new_amounts;
+        self.shares.mint(account_id, shares);
+        shares
+    }
+
+    /// burn `shares` of `account_id` and return the token amounts of them
+    pub fn remove_liquidity(
+        &mut self,
+        account_id: &AccountId,
+        shares: Balance,
+        min_amounts: &[Balance],
+    ) -> Vec<Balance> {
+        self.shares
+            .burn(account_id, shares, &mut self.amounts, min_amounts)
+    }
+
+    /// how many `token_out` would be returned for `amount_in` of `token_in`
+    pub fn get_return(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+    ) -> Balance {
+        self.try_get_return(token_in, amount_in, token_out)
+            .unwrap_or_else(|error| error.panic())
+    }
+
+    /// like `get_return` but return the error instead of panicking
+    pub fn try_get_return(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+    ) -> Result<Balance, DeFiError> {
+        let i = token_index(&self.token_account_ids, token_in).ok_or(DeFiError::UnknownToken)?;
+        let j = token_index(&self.token_account_ids, token_out).ok_or(DeFiError::UnknownToken)?;
+        check(self.amounts.iter().all(|a| *a > 0), DeFiError::EmptyPool)?;
+        let ann = self.ann();
+        let xp = self.normalize(&self.amounts);
+        let d = compute_d(&xp, ann);
+        let amount_in = U256::from(amount_in - fee_of(self.total_fee, amount_in)) * self.rate(i);
+        let y = compute_y(&xp, i, j, xp[i] + amount_in, d, ann);
+        // round down in favor of the pool
+        let dy = if xp[j] > y + 1 {
+            xp[j] - y - 1
+        } else {
+            U256::zero()
+        };
+        Ok((dy / self.rate(j)).as_u128())
+    }
+
+    /// swap `amount_in` of `token_in` into the pool, return the amount of `token_out`
+    pub fn swap(
+        &mut self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+    ) -> Balance {
+        ensure(amount_in > 0, DeFiError::ZeroAmount);
+        let amount_out = self.get_return(token_in, amount_in, token_out);
+        ensure(amount_out > 0, DeFiError::ZeroAmount);
+        let in_idx = assert_token_index(&self.token_account_ids, token_in);
+        let out_idx = assert_token_index(&self.token_account_ids, token_out);
+        self.amounts[in_idx] = add_reserve(self.amounts[in_idx], amount_in);
+        self.amounts[out_idx] -= amount_out;
+        amount_out
+    }
+
+    /// move of the price of `token_in` in `token_out` caused by swapping `amount_in`,
+    /// in basis points
+    pub fn price_impact(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+    ) -> u32 {
+        let amount_out = self.get_return(token_in, amount_in, token_out);
+        let (i, j) = (
+            assert_token_index(&self.token_account_ids, token_in),
+            assert_token_index(&self.token_account_ids, token_out),
+        );
+        let mut amounts = self.amounts.clone();
+        amounts[i] = add_reserve(amounts[i], amount_in);
+        amounts[j] -= amount_out;
+        let ann = self.ann();
+        price_move(
+            marginal_price(&self.normalize(&self.amounts), i, j, ann),
+            marginal_price(&self.normalize(&amounts), i, j, ann),
+        )
+    }
+}
+
+#[near_bindgen]
+impl DeFi {
+    /// create a StableSwap pool of 2 to 4 whitelisted tokens with their decimals, the
+    /// attached deposit pays for its storage and the rest is refunded. return the id of the pool
+    #[payable]
+    pub fn add_stable_swap_pool(
+        &mut self,
+        token_ids: Vec<AccountId>,
+        decimals: Vec<u8>,
+        total_fee: u32,
+        amp_factor: u64,
+    ) -> u64 {
+        ensure(
+            (2..=MAX_STABLE_TOKENS).contains(&token_ids.len())
+                && token_ids
+                    .iter()
+                    .enumerate()
+                    .all(|(i, token_id)| !token_ids[..i].contains(token_id)),
+            DeFiError::InvalidPoolTokens,
+        );
+        for token_id in token_ids.iter() {
+            self.assert_whitelisted(token_id);
+        }
+        let pool_id = self.pools.len();
+        self.internal_add_pool(Pool::StableSwapPool(StableSwapPool::new(
+            pool_id,
+            token_ids,
+            decimals,
+            total_fee,
+            u128::from(amp_factor),
+        )))
+    }
+
+    /// move the amp factor of the pool `pool_id` linearly to `future_amp_factor` until
+    /// `future_amp_time` (nanoseconds), only the owner can call it
+    pub fn ramp_amp(&mut self, pool_id: u64, future_amp_factor: u64, future_amp_time: U64) {
+        self.assert_role(Role::Owner);
+        let mut pool = self.internal_get_stable_swap_pool(pool_id);
+        pool.ramp_amp(u128::from(future_amp_factor), future_amp_time.0);
+        self.pools.replace(pool_id, &Pool::StableSwapPool(pool));
+    }
+
+    /// keep the current amp factor of the pool `pool_id`, only the owner can call it
+    pub fn stop_ramp_amp(&mut self, pool_id: u64) {
+        self.assert_role(Role::Owner);
+        let mut pool = self.internal_get_stable_swap_pool(pool_id);
+        pool.stop_ramp_amp();
+        self.pools.replace(pool_id, &Pool::StableSwapPool(pool));
+    }
+}
+
+impl DeFi {
+    fn internal_get_stable_swap_pool(&self, pool_id: u64) -> StableSwapPool {
+        match self.internal_get_pool(pool_id) {
+            Pool::StableSwapPool(pool) => pool,
+            _ => DeFiError::NotStableSwapPool.panic(),
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+
+    const ONE_A: Balance = 1_000_000;
+    const ONE_B: Balance = 1_000_000_000_000_000_000;
+
+    fn new_pool(amp_factor: u128) -> StableSwapPool {
+        testing_env!(VMContextBuilder::new().build());
+        // token A with 6 decimals, token B with 18
+        StableSwapPool::new(
+            0,
+            vec![accounts(3), accounts(4)],
+            vec![6, 18],
+            0,
+            amp_factor,
+        )
+    }
+
+    #[test]
+    fn swap_close_to_one() {
+        let mut pool = new_pool(100);
+        pool.add_liquidity(&accounts(0), &mut [1_000_000 * ONE_A, 1_000_000 * ONE_B]);
+        assert_eq!(pool.shares.total_supply, 2_000_000 * ONE_B);
+
+        let amount_out = pool.swap(&accounts(3), 10_000 * ONE_A, &accounts(4));
+        // a constant product pool would return about 9_901 B
+        assert!(amount_out > 9_999 * ONE_B && amount_out < 10_000 * ONE_B);
+        assert_eq!(pool.amounts[0], 1_010_000 * ONE_A);
+    }
+
+    #[test]
+    fn price_impact_below_constant_product() {
+        let mut pool = new_pool(100);
+        pool.add_liquidity(&accounts(0), &mut [1_000_000 * ONE_A, 1_000_000 * ONE_B]);
+        // a constant product pool would move the price by about 1736 basis points
+        let impact = pool.price_impact(&accounts(3), 100_000 * ONE_A, &accounts(4));
+        assert_eq!(impact, 10);
+    }
+
+    #[test]
+    fn imbalanced_liquidity_pays_fee() {
+        let mut pool = new_pool(100);
+        pool.total_fee = 30;
+        pool.add_liquidity(&accounts(0), &mut [1_000 * ONE_A, 1_000 * ONE_B]);
+        let balanced = pool.add_liquidity(&accounts(1), &mut [10 * ONE_A, 10 * ONE_B]);
+        let imbalanced = pool.add_liquidity(&accounts(2), &mut [20 * ONE_A, 0]);
+        assert!(imbalanced < balanced);
+
+        // the pool holds more A now, the fee is kept by the pool
+        let amounts = pool.remove_liquidity(&accounts(1), balanced, &[0, 0]);
+        assert!(amounts[0] > 10 * ONE_A && amounts[1] < 10 * ONE_B);
+        assert!(amounts[0] * 1_000_000_000_000 + amounts[1] > 20 * ONE_B);
+    }
+
+    #[test]
+    #[should_panic(expected = "E029: amount must be positive")]
+    fn first_liquidity_with_zero_amount() {
+        let mut pool = new_pool(100);
+        pool.add_liquidity(&accounts(0), &mut [1_000_000 * ONE_A, 0]);
+    }
+
+    #[test]
+    fn ramp_amp_factor() {
+        let mut pool = new_pool(100);
+        pool.ramp_amp(200, MIN_RAMP_DURATION);
+        testing_env!(VMContextBuilder::new()
+            .block_timestamp(MIN_RAMP_DURATION / 2)
+            .build());
+        assert_eq!(pool.amp_factor(), 150);
+        pool.stop_ramp_amp();
+        testing_env!(VMContextBuilder::new()
+            .block_timestamp(MIN_RAMP_DURATION)
+            .build());
+        assert_eq!(pool.amp_factor(), 150);
+    }
+
+    #[test]
+    #[should_panic(expected = "E042: invalid amp ramp")]
+    fn ramp_amp_too_fast() {
+        let mut pool = new_pool(100);
+        pool.ramp_amp(200, MIN_RAMP_DURATION - 1);
+    }
+}
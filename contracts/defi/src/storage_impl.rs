@@ -0,0 +1,157 @@
+use near_contract_standards::storage_management::{
+    StorageBalance, StorageBalanceBounds, StorageManagement,
+};
+use near_sdk::{assert_one_yocto, StorageUsage};
+
+use crate::*;
+
+#[near_bindgen]
+impl StorageManagement for DeFi {
+    /// register `account_id` or add to its storage deposit, the deposit pays for
+    /// the internal balances and LP shares of the account
+    #[payable]
+    fn storage_deposit(
+        &mut self,
+        account_id: Option<AccountId>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance {
+        let amount = env::attached_deposit();
+        let account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+        let registration_only = registration_only.unwrap_or(false);
+        if let Some(mut account) = self.accounts.get(&account_id) {
+            if registration_only {
+                log!("The account is already registered, refunding the deposit");
+                if amount > 0 {
+                    Promise::new(env::predecessor_account_id()).transfer(amount);
+                }
+            } else {
+                account.near_amount += amount;
+                self.accounts.insert(&account_id, &account);
+            }
+        } else {
+            let min_balance = self.storage_balance_bounds().min.0;
+            ensure(amount >= min_balance, DeFiError::NotEnoughStorageDeposit);
+            let near_amount = if registration_only {
+                let refund = amount - min_balance;
+                if refund > 0 {
+                    Promise::new(env::predecessor_account_id()).transfer(refund);
+                }
+                min_balance
+            } else {
+                amount
+            };
+            self.accounts.insert(
+                &account_id,
+                &Account::new(near_amount, self.account_storage_usage),
+            );
+        }
+        self.internal_storage_balance_of(&account_id).unwrap()
+    }
+
+    /// withdraw the part of the storage deposit which isn't used
+    #[payable]
+    fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let mut account = self.internal_unwrap_account(&account_id);
+        let available = account.storage_available();
+        let amount = amount.map_or(available, |amount| amount.0);
+        ensure(amount <= available, DeFiError::NotEnoughStorageDeposit);
+        if amount > 0 {
+            account.near_amount -= amount;
+            self.accounts.insert(&account_id, &account);
+            Promise::new(account_id.clone()).transfer(amount);
+        }
+        self.internal_storage_balance_of(&account_id).unwrap()
+    }
+
+    /// only accounts without internal balances and LP shares can be unregistered,
+    /// `force` isn't supported
+    #[payable]
+    fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        assert_one_yocto();
+        ensure(!force.unwrap_or(false), DeFiError::AccountNotEmpty);
+        let account_id = env::predecessor_account_id();
+        self.assert_no_flash_swap(&account_id);
+        if let Some(account) = self.accounts.get(&account_id) {
+            ensure(
+                account.tokens.is_empty()
+                    && !self.limit_orders.has_orders(&account_id)
+                    && self
+                        .referrals
+                        .get(&account_id)
+                        .is_none_or(|referral| referral.unclaimed.is_empty())
+                    && account.pools.is_empty(),
+                DeFiError::AccountNotEmpty,
+            );
+            self.accounts.remove(&account_id);
+            self.referrals.remove(&account_id);
+            Promise::new(account_id).transfer(account.near_amount);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// there is no upper bound, every token held and every pool joined needs more storage
+    fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        StorageBalanceBounds {
+            min: U128(Balance::from(self.account_storage_usage) * env::storage_byte_cost()),
+            max: None,
+        }
+    }
+
+    fn storage_balance_of(&self, account_id: AccountId) -> Option<StorageBalance> {
+        self.internal_storage_balance_of(&account_id)
+    }
+}
+
+impl DeFi {
+    /// storage of an empty account with the longest account id
+    pub(crate) fn measure_account_storage_usage(&mut self) {
+        let initial_storage_usage = env::storage_usage();
+        let tmp_account_id = AccountId::new_unchecked("a".repeat(64));
+        self.accounts.insert(&tmp_account_id, &Account::default());
+        self.account_storage_usage = env::storage_usage() - initial_storage_usage;
+        self.accounts.remove(&tmp_account_id);
+    }
+
+    fn internal_storage_balance_of(&self, account_id: &AccountId) -> Option<StorageBalance> {
+        self.accounts.get(account_id).map(|account| StorageBalance {
+            total: U128(account.near_amount),
+            available: U128(account.storage_available()),
+        })
+    }
+
+    /// charge `account_id` for the storage added since `prev_storage`, or give back
+    /// the storage released, and check the storage deposit still covers it
+    pub(crate) fn internal_update_storage(
+        &mut self,
+        account_id: &AccountId,
+        prev_storage: StorageUsage,
+    ) {
+        let account = self.internal_track_storage(account_id, prev_storage);
+        ensure(
+            account.storage_needed() <= account.near_amount,
+            DeFiError::NotEnoughStorageDeposit,
+        );
+    }
+
+    /// like `internal_update_storage` but without the check, for callbacks which can't fail
+    pub(crate) fn internal_track_storage(
+        &mut self,
+        account_id: &AccountId,
+        prev_storage: StorageUsage,
+    ) -> Account {
+        let mut account = self.internal_unwrap_account(account_id);
+        let storage = env::storage_usage();
+        if storage > prev_storage {
+            account.storage_used += storage - prev_storage;
+        } else {
+            account.storage_used = account.storage_used.saturating_sub(prev_storage - storage);
+        }
+        // `storage_used` has a fixed size, saving it doesn't change the usage again
+        self.accounts.insert(account_id, &account);
+        account
+    }
+}
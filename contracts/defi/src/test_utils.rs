@@ -0,0 +1,78 @@
+//! Fixtures shared by the unit tests of the modules.
+use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+use near_contract_standards::storage_management::StorageManagement;
+use near_sdk::test_utils::{accounts, VMContextBuilder};
+use near_sdk::testing_env;
+
+use crate::*;
+
+pub const STORAGE_DEPOSIT: Balance = 100_000_000_000_000_000_000_000;
+
+/// contract of `accounts(0)` with TokenA `accounts(3)` and TokenB `accounts(4)` in pool 0,
+/// called by `accounts(0)`
+pub fn new_contract() -> (DeFi, VMContextBuilder) {
+    let mut context = VMContextBuilder::new();
+    context
+        .current_account_id(accounts(0))
+        .predecessor_account_id(accounts(0));
+    testing_env!(context.build());
+    let contract = DeFi::new(
+        accounts(0),
+        TokenConfig {
+            address: accounts(3),
+            ticker: "TokenA".into(),
+        },
+        TokenConfig {
+            address: accounts(4),
+            ticker: "TokenB".into(),
+        },
+    );
+    (contract, context)
+}
+
+/// `new_contract` with `amount` of TokenA and TokenB added to pool 0 by `accounts(0)`
+pub fn pool_contract(amount: Balance) -> (DeFi, VMContextBuilder) {
+    let (mut contract, mut context) = new_contract();
+    deposit(
+        &mut contract,
+        &mut context,
+        accounts(0),
+        accounts(3),
+        amount,
+    );
+    deposit(
+        &mut contract,
+        &mut context,
+        accounts(0),
+        accounts(4),
+        amount,
+    );
+    contract.add_liquidity(0, vec![U128(amount), U128(amount)], None);
+    (contract, context)
+}
+
+pub fn register(contract: &mut DeFi, context: &mut VMContextBuilder, account_id: AccountId) {
+    testing_env!(context
+        .predecessor_account_id(account_id)
+        .attached_deposit(STORAGE_DEPOSIT)
+        .build());
+    contract.storage_deposit(None, None);
+    testing_env!(context.attached_deposit(0).build());
+}
+
+/// send `amount` of `token_id` to the contract on behalf of `account_id`,
+/// registering the account first. `account_id` is the predecessor afterwards
+pub fn deposit(
+    contract: &mut DeFi,
+    context: &mut VMContextBuilder,
+    account_id: AccountId,
+    token_id: AccountId,
+    amount: Balance,
+) {
+    if contract.storage_balance_of(account_id.clone()).is_none() {
+        register(contract, context, account_id.clone());
+    }
+    testing_env!(context.predecessor_account_id(token_id).build());
+    contract.ft_on_transfer(account_id.clone(), U128(amount), "".into());
+    testing_env!(context.predecessor_account_id(account_id).build());
+}
@@ -0,0 +1,41 @@
+use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+use near_sdk::PromiseOrValue;
+
+use crate::events::{Deposit, Refund};
+use crate::*;
+
+#[near_bindgen]
+impl FungibleTokenReceiver for DeFi {
+    /// whitelisted tokens are credited to the internal balance of `sender_id`, who has to be
+    /// registered with `storage_deposit`. they are refunded in full while adding liquidity is paused
+    fn ft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        let token_id = env::predecessor_account_id();
+        self.assert_whitelisted(&token_id);
+        ensure(msg.is_empty(), DeFiError::InvalidMessage);
+        if self.paused.add_liquidity {
+            Refund {
+                account_id: &sender_id,
+                token_id: &token_id,
+                amount: &amount,
+                reason: "add liquidity is paused",
+            }
+            .emit();
+            return PromiseOrValue::Value(amount);
+        }
+        let prev_storage = env::storage_usage();
+        self.internal_deposit(&sender_id, &token_id, amount.0);
+        self.internal_update_storage(&sender_id, prev_storage);
+        Deposit {
+            account_id: &sender_id,
+            token_id: &token_id,
+            amount: &amount,
+        }
+        .emit();
+        PromiseOrValue::Value(U128(0))
+    }
+}
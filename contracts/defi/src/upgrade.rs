@@ -0,0 +1,203 @@
+use crate::*;
+
+/// storage key of the layout version of the stored `DeFi` state
+const STATE_VERSION_KEY: &[u8] = b"STATE_VERSION";
+/// layout version of `DeFi`, bump it and add a `VersionedDeFi` variant
+/// for the old layout whenever a released layout changes
+pub const STATE_VERSION: u32 = 1;
+/// gas kept by `upgrade` for deploying the code
+const GAS_FOR_DEPLOY: Gas = Gas(20 * TGAS);
+
+/// layout of `DeFi` before the state was versioned
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct DeFiV0 {
+    owner_id: AccountId,
+    address_a: AccountId,
+    address_b: AccountId,
+    ratio: U128,
+    tokens: LookupMap<String, TokenInfo>,
+    tickers: LookupMap<AccountId, String>,
+    pending: bool,
+}
+
+/// every layout the stored state may have
+// read once per migration, the size of the variants doesn't matter
+#[allow(clippy::large_enum_variant)]
+pub enum VersionedDeFi {
+    V0(DeFiV0),
+    V1(DeFi),
+}
+
+impl VersionedDeFi {
+    /// read the stored state with the layout recorded under `STATE_VERSION_KEY`,
+    /// state written before the key existed is `V0`
+    pub fn read() -> Self {
+        let version = env::storage_read(STATE_VERSION_KEY)
+            .map(|v| {
+                u32::try_from_slice(&v).unwrap_or_else(|_| DeFiError::UnknownStateVersion.panic())
+            })
+            .unwrap_or(0);
+        match version {
+            0 => VersionedDeFi::V0(read_state()),
+            1 => VersionedDeFi::V1(read_state()),
+            _ => DeFiError::UnknownStateVersion.panic(),
+        }
+    }
+}
+
+impl From<VersionedDeFi> for DeFi {
+    fn from(state: VersionedDeFi) -> Self {
+        match state {
+            VersionedDeFi::V0(mut old) => {
+                let token_address = |symbol: &str| {
+                    old.tokens
+                        .get(&symbol.to_string())
+                        .unwrap_or_else(|| DeFiError::TokenInfoNotFound.panic())
+                        .contract_address
+                };
+                // the V0 reserves stayed with the token_a / token_b subaccounts,
+                // the pool starts empty
+                let token_ids = vec![token_address("TokenA"), token_address("TokenB")];
+                let mut whitelisted_tokens = UnorderedSet::new(StoreKey::Whitelist);
+                whitelisted_tokens.extend(token_ids.iter().cloned());
+                let mut pools = Vector::new(StoreKey::Pools);
+                pools.push(&Pool::SimplePool(SimplePool::new(
+                    0,
+                    token_ids.clone(),
+                    DEFAULT_TOTAL_FEE,
+                )));
+                // the V0 maps can't be iterated, only the pool tokens are known
+                let mut tokens = UnorderedMap::new(StoreKey::Tokens);
+                let mut tickers = UnorderedMap::new(StoreKey::Tickers);
+                for symbol in ["TokenA".to_string(), "TokenB".to_string()] {
+                    if let Some(token_info) = old.tokens.remove(&symbol) {
+                        tokens.insert(&symbol, &token_info);
+                    }
+                }
+                for token_id in token_ids.iter() {
+                    if let Some(ticker) = old.tickers.remove(token_id) {
+                        tickers.insert(token_id, &ticker);
+                    }
+                }
+                let mut state = DeFi {
+                    owner_id: old.owner_id,
+                    pending_owner_id: None,
+                    tokens,
+                    tickers,
+                    pools,
+                    whitelisted_tokens,
+                    paused: PauseFlags::default(),
+                    next_swap_id: 0,
+                    roles: LookupMap::new(StoreKey::Roles),
+                    accounts: LookupMap::new(StoreKey::Accounts),
+                    account_storage_usage: 0,
+                    gas_config: GasConfig::default(),
+                    oracle: TwapOracle::new(),
+                    limit_orders: LimitOrderBook::new(),
+                    flash_swaps: LookupMap::new(StoreKey::FlashSwaps),
+                    wnear_id: None,
+                    circuit_breaker: CircuitBreaker::default(),
+                    price_windows: LookupMap::new(StoreKey::PriceWindows),
+                    referral_fee: 0,
+                    referrals: LookupMap::new(StoreKey::Referrals),
+                    flash_swap_pools: LookupMap::new(StoreKey::FlashSwapPools),
+                    lost_found: LookupMap::new(StoreKey::LostFound),
+                };
+                state.measure_account_storage_usage();
+                state
+            }
+            VersionedDeFi::V1(state) => state,
+        }
+    }
+}
+
+fn read_state<T: BorshDeserialize>() -> T {
+    env::state_read().unwrap_or_else(|| DeFiError::StateNotFound.panic())
+}
+
+pub(crate) fn write_state_version() {
+    env::storage_write(STATE_VERSION_KEY, &STATE_VERSION.try_to_vec().unwrap());
+}
+
+#[near_bindgen]
+impl DeFi {
+    /// deploy the new wasm code passed as the raw input and call `migrate` on it,
+    /// only the owner can call it
+    pub fn upgrade(&self) -> Promise {
+        self.assert_role(Role::Owner);
+        let code = env::input().unwrap_or_else(|| DeFiError::NoCodeToDeploy.panic());
+        let gas = env::prepaid_gas() - env::used_gas() - GAS_FOR_DEPLOY;
+        Promise::new(env::current_account_id())
+            .deploy_contract(code)
+            .function_call("migrate".to_string(), vec![], 0, gas)
+    }
+
+    /// convert the stored state of any known layout into the current one
+    #[private]
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        let state: DeFi = VersionedDeFi::read().into();
+        write_state_version();
+        state
+    }
+
+    pub fn version(&self) -> String {
+        VERSION.to_string()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+
+    #[test]
+    fn test_migrate_from_v0() {
+        testing_env!(VMContextBuilder::new()
+            .current_account_id(accounts(0))
+            .predecessor_account_id(accounts(0))
+            .build());
+        let mut tokens = LookupMap::new(StoreKey::Token);
+        tokens.insert(
+            &"TokenA".to_string(),
+            &TokenInfo {
+                contract_address: accounts(3),
+                name: "fungible token A".into(),
+                symbol: "TokenA".into(),
+                decimals: 8,
+            },
+        );
+        tokens.insert(
+            &"TokenB".to_string(),
+            &TokenInfo {
+                contract_address: accounts(4),
+                name: "fungible token B".into(),
+                symbol: "TokenB".into(),
+                decimals: 8,
+            },
+        );
+        env::state_write(&DeFiV0 {
+            owner_id: accounts(1),
+            address_a: accounts(3),
+            address_b: accounts(4),
+            ratio: U128(0),
+            tokens,
+            tickers: LookupMap::new(StoreKey::Decimals),
+            pending: false,
+        });
+
+        let contract = DeFi::migrate();
+        let info = contract.get_pool_info();
+        assert_eq!(info.owner_id, accounts(1));
+        assert_eq!(info.token_a.unwrap().symbol, "TokenA");
+        assert_eq!(info.total_fee, DEFAULT_TOTAL_FEE);
+        assert_eq!(info.reserve_a.0, 0);
+        assert_eq!(contract.get_tokens(None, None).len(), 2);
+        assert_eq!(contract.version(), VERSION);
+
+        env::state_write(&contract);
+        assert!(matches!(VersionedDeFi::read(), VersionedDeFi::V1(_)));
+    }
+}
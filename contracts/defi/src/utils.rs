@@ -0,0 +1,68 @@
+use near_sdk::borsh::BorshDeserialize;
+use near_sdk::collections::Vector;
+
+// the code generated by `construct_uint!` doesn't follow every clippy lint
+#[allow(clippy::all)]
+mod uint {
+    use uint::construct_uint;
+
+    construct_uint! {
+        /// 256-bit unsigned integer, products of two balances don't overflow it
+        pub struct U256(4);
+    }
+}
+
+pub use self::uint::U256;
+use crate::{Balance, FEE_DIVISOR, PRICE_PRECISION};
+
+/// price of a token in the other token of a constant product pair of reserves,
+/// `PRICE_PRECISION` times `reserve_out / reserve_in`
+pub fn reserve_price(reserve_in: U256, reserve_out: U256) -> U256 {
+    reserve_out * U256::from(PRICE_PRECISION) / reserve_in
+}
+
+/// move from the price `before` to `after` in basis points, saturating
+pub fn price_move(before: U256, after: U256) -> u32 {
+    if before.is_zero() {
+        return 0;
+    }
+    let delta = if after > before {
+        after - before
+    } else {
+        before - after
+    };
+    let move_bps = delta * U256::from(FEE_DIVISOR) / before;
+    if move_bps > U256::from(u32::MAX) {
+        u32::MAX
+    } else {
+        move_bps.as_u32()
+    }
+}
+
+/// reserves of a pair after swapping `amount_in` for `amount_out`
+pub fn reserves_after(
+    reserve_in: Balance,
+    amount_in: Balance,
+    reserve_out: Balance,
+    amount_out: Balance,
+) -> (U256, U256) {
+    (
+        U256::from(reserve_in) + U256::from(amount_in),
+        U256::from(reserve_out - amount_out),
+    )
+}
+
+/// `limit` elements of `vector` from `from_index`, all the rest if `limit` is `None`
+pub fn paginate<T: BorshDeserialize>(
+    vector: &Vector<T>,
+    from_index: Option<u64>,
+    limit: Option<u64>,
+) -> Vec<T> {
+    let from_index = from_index.unwrap_or(0);
+    let end = limit.map_or(vector.len(), |limit| {
+        std::cmp::min(from_index.saturating_add(limit), vector.len())
+    });
+    (from_index..end)
+        .filter_map(|index| vector.get(index))
+        .collect()
+}
@@ -0,0 +1,363 @@
+use near_sdk::{AccountId, Balance};
+
+use crate::errors::{check, ensure};
+use crate::pool::{assert_token_index, token_index, PoolShares};
+use crate::simple_pool::add_reserve;
+use crate::utils::{price_move, reserve_price, reserves_after, U256};
+use crate::*;
+
+/// weights of a pool are in basis points and add up to this
+pub const WEIGHT_DIVISOR: u32 = 10_000;
+/// every token weighs at least 1%
+pub const MIN_WEIGHT: u32 = 100;
+pub const MAX_WEIGHTED_TOKENS: usize = 8;
+/// a swap can add at most half of the reserve of `token_in`, the power series
+/// converges slowly for larger swaps
+const MAX_IN_RATIO: u128 = ONE / 2;
+
+/// 1.0 in the fixed point math of the weighted product
+const ONE: u128 = 1_000_000_000_000_000_000;
+/// the power series stops when its terms are smaller than this
+const POW_PRECISION: u128 = 100_000_000;
+/// bound of the rounding error of `pow_with_error`, half a unit for each of its few dozen steps
+const POW_ROUNDING_ERROR: u128 = 128;
+
+fn mul(a: U256, b: U256) -> U256 {
+    (a * b + U256::from(ONE / 2)) / U256::from(ONE)
+}
+
+fn div(a: U256, b: U256) -> U256 {
+    (a * U256::from(ONE) + b / 2) / b
+}
+
+fn div_down(a: U256, b: U256) -> U256 {
+    a * U256::from(ONE) / b
+}
+
+fn div_up(a: U256, b: U256) -> U256 {
+    (a * U256::from(ONE) + b - 1) / b
+}
+
+/// `base^n` for an integer `n`
+fn pow_int(mut base: U256, mut n: U256) -> U256 {
+    let mut result = U256::from(ONE);
+    while !n.is_zero() {
+        if n.bit(0) {
+            result = mul(result, base);
+        }
+        base = mul(base, base);
+        n >>= 1;
+    }
+    result
+}
+
+/// `base^exp` rounded up, for the amounts in favor of the pool
+fn pow_up(base: U256, exp: U256) -> U256 {
+    let (pow, error) = pow_with_error(base, exp);
+    pow + error
+}
+
+/// `base^exp` with `base` in (0, 2) and a bound of its error. the integer part of `exp`
+/// is exact, the fraction is the binomial series of `(1 + (base - 1))^fraction`. with
+/// `base` within a third of 1 the tail of the series is smaller than its last term
+fn pow_with_error(base: U256, exp: U256) -> (U256, U256) {
+    let one = U256::from(ONE);
+    let whole = exp / one;
+    let fraction = exp % one;
+    let whole_pow = pow_int(base, whole);
+    let rounding = U256::from(POW_ROUNDING_ERROR);
+    if fraction.is_zero() {
+        return (whole_pow, rounding);
+    }
+    let (x, x_neg) = if base >= one {
+        (base - one, false)
+    } else {
+        (one - base, true)
+    };
+    let mut term = one;
+    let mut sum = one;
+    let mut negative = false;
+    let mut k = U256::one();
+    while term >= U256::from(POW_PRECISION) {
+        // term_k = term_{k-1} * (fraction - (k - 1)) * x / k
+        let big_k = k * one;
+        let (c, c_neg) = if fraction >= big_k - one {
+            (fraction - (big_k - one), false)
+        } else {
+            (big_k - one - fraction, true)
+        };
+        term = div(mul(term, mul(c, x)), big_k);
+        if term.is_zero() {
+            break;
+        }
+        negative ^= x_neg ^ c_neg;
+        if negative {
+            sum -= term;
+        } else {
+            sum += term;
+        }
+        k += U256::one();
+    }
+    (mul(whole_pow, sum), term + rounding)
+}
+
+/// weighted product pool, prod(x_i ^ w_i) = k. the spot price of a token is
+/// `(reserve_out / w_out) / (reserve_in / w_in)`, so an 80/20 pool keeps 80% of its
+/// value in the first token
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct WeightedPool {
+    /// token contracts of the pool, the index of a token is used by `amounts` and `weights`
+    pub token_account_ids: Vec<AccountId>,
+    /// reserves of each token
+    pub amounts: Vec<Balance>,
+    /// weights of each token in basis points, fixed at creation
+    pub weights: Vec<u32>,
+    /// swap fee in basis points
+    pub total_fee: u32,
+    pub shares: PoolShares,
+}
+
+impl WeightedPool {
+    pub fn new(
+        pool_id: u64,
+        token_account_ids: Vec<AccountId>,
+        weights: Vec<u32>,
+        total_fee: u32,
+    ) -> Self {
+        ensure(total_fee < FEE_DIVISOR, DeFiError::FeeTooLarge);
+        ensure(
+            weights.len() == token_account_ids.len()
+                && weights.iter().all(|w| *w >= MIN_WEIGHT)
+                && weights.iter().sum::<u32>() == WEIGHT_DIVISOR,
+            DeFiError::InvalidWeights,
+        );
+        Self {
+            amounts: vec![0; token_account_ids.len()],
+            token_account_ids,
+            weights,
+            total_fee,
+            shares: PoolShares::new(pool_id),
+        }
+    }
+
+    /// add liquidity in proportion to the reserves, `amounts` is updated to the amounts used.
+    /// the first liquidity sets the prices. return the shares minted to `account_id`
+    pub fn add_liquidity(&mut self, account_id: &AccountId, amounts: &mut [Balance]) -> Balance {
+        let shares = self.shares.add_in_proportion(&mut self.amounts, amounts);
+        self.shares.mint(account_id, shares);
+        shares
+    }
+
+    /// burn `shares` of `account_id` and return the token amounts of them
+    pub fn remove_liquidity(
+        &mut self,
+        account_id: &AccountId,
+        shares: Balance,
+        min_amounts: &[Balance],
+    ) -> Vec<Balance> {
+        self.shares
+            .burn(account_id, shares, &mut self.amounts, min_amounts)
+    }
+
+    /// how many `token_out` would be returned for `amount_in` of `token_in`,
+    /// `out = reserve_out * (1 - (reserve_in / (reserve_in + amount_in))^(w_in / w_out))`
+    pub fn get_return(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+    ) -> Balance {
+        self.try_get_return(token_in, amount_in, token_out)
+            .unwrap_or_else(|error| error.panic())
+    }
+
+    /// like `get_return` but return the error instead of panicking
+    pub fn try_get_return(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+    ) -> Result<Balance, DeFiError> {
+        let in_idx =
+            token_index(&self.token_account_ids, token_in).ok_or(DeFiError::UnknownToken)?;
+        let out_idx =
+            token_index(&self.token_account_ids, token_out).ok_or(DeFiError::UnknownToken)?;
+        let in_balance = U256::from(self.amounts[in_idx]);
+        let out_balance = U256::from(self.amounts[out_idx]);
+        check(
+            !in_balance.is_zero() && !out_balance.is_zero(),
+            DeFiError::EmptyPool,
+        )?;
+        check(
+            U256::from(amount_in) <= mul(in_balance, U256::from(MAX_IN_RATIO)),
+            DeFiError::MaxInRatioExceeded,
+        )?;
+        // round down in favor of the pool: the input after the fee and the exponent down,
+        // the base and the power up. `MAX_IN_RATIO` keeps the base within a third of 1
+        let amount_with_fee = U256::from(amount_in) * U256::from(FEE_DIVISOR - self.total_fee)
+            / U256::from(FEE_DIVISOR);
+        let base = div_up(in_balance, in_balance + amount_with_fee);
+        let exp = div_down(
+            U256::from(self.weights[in_idx]),
+            U256::from(self.weights[out_idx]),
+        );
+        let one = U256::from(ONE);
+        let ratio = one.saturating_sub(pow_up(base, exp));
+        Ok((out_balance * ratio / one).as_u128())
+    }
+
+    /// swap `amount_in` of `token_in` into the pool, return the amount of `token_out`
+    pub fn swap(
+        &mut self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+    ) -> Balance {
+        ensure(amount_in > 0, DeFiError::ZeroAmount);
+        let amount_out = self.get_return(token_in, amount_in, token_out);
+        ensure(amount_out > 0, DeFiError::ZeroAmount);
+        let in_idx = assert_token_index(&self.token_account_ids, token_in);
+        let out_idx = assert_token_index(&self.token_account_ids, token_out);
+        self.amounts[in_idx] = add_reserve(self.amounts[in_idx], amount_in);
+        self.amounts[out_idx] -= amount_out;
+        amount_out
+    }
+
+    /// move of the price of `token_in` in `token_out` caused by swapping `amount_in`,
+    /// in basis points. the weights scale the price by a constant, so it moves like
+    /// the ratio of the reserves
+    pub fn price_impact(
+        &self,
+        token_in: &AccountId,
+        amount_in: Balance,
+        token_out: &AccountId,
+    ) -> u32 {
+        let amount_out = self.get_return(token_in, amount_in, token_out);
+        let in_balance = self.amounts[assert_token_index(&self.token_account_ids, token_in)];
+        let out_balance = self.amounts[assert_token_index(&self.token_account_ids, token_out)];
+        let (in_after, out_after) = reserves_after(in_balance, amount_in, out_balance, amount_out);
+        price_move(
+            reserve_price(U256::from(in_balance), U256::from(out_balance)),
+            reserve_price(in_after, out_after),
+        )
+    }
+}
+
+#[near_bindgen]
+impl DeFi {
+    /// create a weighted pool of 2 to 8 whitelisted tokens, `weights` are in basis points
+    /// and add up to 10000. the attached deposit pays for its storage and the rest is
+    /// refunded. return the id of the pool
+    #[payable]
+    pub fn add_weighted_pool(
+        &mut self,
+        token_ids: Vec<AccountId>,
+        weights: Vec<u32>,
+        total_fee: u32,
+    ) -> u64 {
+        ensure(
+            (2..=MAX_WEIGHTED_TOKENS).contains(&token_ids.len())
+                && token_ids
+                    .iter()
+                    .enumerate()
+                    .all(|(i, token_id)| !token_ids[..i].contains(token_id)),
+            DeFiError::InvalidPoolTokens,
+        );
+        for token_id in token_ids.iter() {
+            self.assert_whitelisted(token_id);
+        }
+        let pool_id = self.pools.len();
+        self.internal_add_pool(Pool::WeightedPool(WeightedPool::new(
+            pool_id, token_ids, weights, total_fee,
+        )))
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::accounts;
+
+    use super::*;
+
+    fn fixed(value: f64) -> U256 {
+        U256::from((value * ONE as f64) as u128)
+    }
+
+    fn to_f64(value: U256) -> f64 {
+        value.as_u128() as f64 / ONE as f64
+    }
+
+    #[test]
+    fn pow_fraction() {
+        assert!((to_f64(pow_up(fixed(0.5), fixed(0.25))) - 0.5f64.powf(0.25)).abs() < 1e-9);
+        assert!((to_f64(pow_up(fixed(0.9), fixed(4.0))) - 0.9f64.powi(4)).abs() < 1e-9);
+        assert!((to_f64(pow_up(fixed(0.7), fixed(2.5))) - 0.7f64.powf(2.5)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn equal_weights_match_constant_product() {
+        let mut pool = WeightedPool::new(0, vec![accounts(3), accounts(4)], vec![5000, 5000], 0);
+        pool.add_liquidity(&accounts(0), &mut [1000, 1000]);
+        assert_eq!(pool.swap(&accounts(3), 100, &accounts(4)), 90);
+        assert_eq!(pool.amounts, vec![1100, 910]);
+    }
+
+    #[test]
+    fn rounds_down_like_constant_product() {
+        const RESERVE: Balance = 1_000_000_000_000_000_000_000_000;
+        let mut pool = WeightedPool::new(0, vec![accounts(3), accounts(4)], vec![5000, 5000], 0);
+        pool.add_liquidity(&accounts(0), &mut [RESERVE, RESERVE]);
+        for amount in [
+            7,
+            1_000_000_001,
+            RESERVE / 3 + 1,
+            RESERVE / 7,
+            RESERVE / 2 - 1,
+        ] {
+            // `RESERVE * amount / (RESERVE + amount)` rounded down
+            let floor =
+                (U256::from(RESERVE) * U256::from(amount) / U256::from(RESERVE + amount)).as_u128();
+            let amount_out = pool.get_return(&accounts(3), amount, &accounts(4));
+            assert!(amount_out <= floor && floor - amount_out <= RESERVE / 1_000_000_000_000_000);
+        }
+    }
+
+    #[test]
+    fn swap_80_20() {
+        let mut pool = WeightedPool::new(0, vec![accounts(3), accounts(4)], vec![8000, 2000], 0);
+        // token 3 is worth (4000 / 20) / (1000 / 80) = 16 of token 4
+        pool.add_liquidity(&accounts(0), &mut [1_000_000, 4_000_000]);
+        let amount_out = pool.get_return(&accounts(3), 1_000, &accounts(4));
+        assert!((15_900..16_000).contains(&amount_out));
+        let amount_out = pool.get_return(&accounts(4), 16_000, &accounts(3));
+        assert!((990..1_000).contains(&amount_out));
+    }
+
+    #[test]
+    fn three_tokens() {
+        let mut pool = WeightedPool::new(
+            0,
+            vec![accounts(2), accounts(3), accounts(4)],
+            vec![5000, 2500, 2500],
+            0,
+        );
+        pool.add_liquidity(&accounts(0), &mut [2_000_000, 1_000_000, 1_000_000]);
+        let amount_out = pool.swap(&accounts(3), 1_000, &accounts(4));
+        assert!((990..1_000).contains(&amount_out));
+        assert_eq!(pool.amounts[0], 2_000_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "E045: invalid weights")]
+    fn weights_not_adding_up() {
+        WeightedPool::new(0, vec![accounts(3), accounts(4)], vec![8000, 1000], 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "E046: amount exceeds the max in ratio")]
+    fn swap_too_large() {
+        let mut pool = WeightedPool::new(0, vec![accounts(3), accounts(4)], vec![8000, 2000], 0);
+        pool.add_liquidity(&accounts(0), &mut [1000, 1000]);
+        pool.swap(&accounts(3), 501, &accounts(4));
+    }
+}
@@ -0,0 +1,50 @@
+use crate::utils::paginate;
+use crate::*;
+
+/// only whitelisted tokens can be deposited and paired in new pools, so an arbitrary
+/// NEP-141 contract can't be pooled
+pub trait TokenWhitelist {
+    /// only the owner can call it
+    fn add_whitelisted_tokens(&mut self, token_ids: Vec<AccountId>);
+
+    /// pools of removed tokens keep working, new deposits of them are rejected.
+    /// only the owner can call it
+    fn remove_whitelisted_tokens(&mut self, token_ids: Vec<AccountId>);
+
+    fn get_whitelisted_tokens(&self, from_index: Option<u64>, limit: Option<u64>)
+        -> Vec<AccountId>;
+}
+
+#[near_bindgen]
+impl TokenWhitelist for DeFi {
+    fn add_whitelisted_tokens(&mut self, token_ids: Vec<AccountId>) {
+        self.assert_role(Role::Owner);
+        for token_id in token_ids.iter() {
+            self.whitelisted_tokens.insert(token_id);
+        }
+    }
+
+    fn remove_whitelisted_tokens(&mut self, token_ids: Vec<AccountId>) {
+        self.assert_role(Role::Owner);
+        for token_id in token_ids.iter() {
+            self.whitelisted_tokens.remove(token_id);
+        }
+    }
+
+    fn get_whitelisted_tokens(
+        &self,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<AccountId> {
+        paginate(self.whitelisted_tokens.as_vector(), from_index, limit)
+    }
+}
+
+impl DeFi {
+    pub(crate) fn assert_whitelisted(&self, token_id: &AccountId) {
+        ensure(
+            self.whitelisted_tokens.contains(token_id),
+            DeFiError::TokenNotWhitelisted,
+        );
+    }
+}
@@ -1,5 +1,5 @@
 use near_contract_standards::fungible_token::metadata::FungibleTokenMetadata;
-use near_sdk::json_types::U128;
+use near_sdk::json_types::{U128, U64};
 use near_units::{parse_gas, parse_near};
 use serde_json::{json, Value};
 use workspaces::{Account, Contract};
@@ -103,7 +103,7 @@ async fn main() -> anyhow::Result<()> {
     assert!(result.is_success());
 
     // set the token info
-    for (prefix, id) in [("token_a", ft_contract_a.id()), ("token_b", ft_contract_b.id())].into_iter() {
+    for id in [ft_contract_a.id(), ft_contract_b.id()].into_iter() {
         let result = defi_contract
             .call("set_token_info")
             .args_json(json!({ "token_address": id }))
@@ -112,12 +112,12 @@ async fn main() -> anyhow::Result<()> {
             .await?;
         assert!(result.is_success(), "set token info error");
 
-        // register
+        // register the defi contract address in token contract
         let result = defi_contract
             .as_account()
             .call(id, "storage_deposit")
             .args_json(serde_json::json!({
-                "account_id": format!("{}.{}", prefix, defi_contract.id())
+                "account_id": defi_contract.id()
             }))
             .deposit(parse_near!("0.008 N"))
             .transact()
@@ -139,10 +139,7 @@ async fn main() -> anyhow::Result<()> {
         .json()?;
     println!("get token info: {:?}", token_info);
 
-    // register the defi contract address in token contract
-
-
-    // transfer some token to alice and bob
+    // transfer some token to the defi owner and alice
     let amount1 = U128::from(parse_near!("1,000 N"));
     println!("transfer TokenA");
     transfer_balance(&owner, defi_contract.as_account(), &ft_contract_a, amount1).await?;
@@ -152,53 +149,34 @@ async fn main() -> anyhow::Result<()> {
     transfer_balance(&owner, defi_contract.as_account(), &ft_contract_b, amount1).await?;
     transfer_balance(&owner, &alice, &ft_contract_b, amount1).await?;
 
-    let pool_balance_a: U128 = defi_contract
-        .as_account()
-        .call(defi_contract.id(), "get_swap_token")
-        .args_json(json!({"symbol": "TokenA"}))
-        .gas(parse_gas!("300 Tgas") as u64)
-        .transact()
-        .await?
-        .json()?;
-    println!("get swap TokenA: {:?}", pool_balance_a);
-
-    // deposit  TokenA to Swap
-    for symbol in ["TokenA", "TokenB"] {
-        let result = defi_contract
-            .as_account()
-            .call(defi_contract.id(), "deposit_token")
-            .args_json(json!({"symbol": symbol, "amount": "100"}))
-            .deposit(1)
-            .gas(parse_gas!("300 Tgas") as u64)
+    // register the defi owner and alice in the defi contract
+    for user in [defi_contract.as_account(), &alice] {
+        let result = user
+            .call(defi_contract.id(), "storage_deposit")
+            .args_json(json!({}))
+            .deposit(parse_near!("0.1 N"))
             .transact()
             .await?;
-        println!("defi deposit is success: {:?}", result.is_success());
+        assert!(result.is_success(), "storage deposit failed");
     }
 
-    // get the TokenA
-    let result: U128 = defi_contract
-        .as_account()
-        .call(defi_contract.id(), "get_swap_token")
-        .args_json(json!({"symbol": "TokenA"}))
-        .gas(parse_gas!("300 Tgas") as u64)
+    // deposit TokenA and TokenB, then add them to the pool
+    for contract in [&ft_contract_a, &ft_contract_b] {
+        deposit(defi_contract.as_account(), contract, &defi_contract, U128(1000)).await?;
+    }
+    let result = defi_contract
+        .call("add_liquidity")
+        .args_json(json!({"pool_id": 0, "amounts": ["1000", "1000"]}))
         .transact()
-        .await?
-        .json()?;
-    println!("TokenA in Swap: {:?}", result);
-
+        .await?;
+    assert!(result.is_success(), "add liquidity failed");
 
     // get the ratio
-    let result: U128 = defi_contract
-        .as_account()
-        .call(defi_contract.id(), "get_token_ratio")
-        .args_json(json!({}))
-        .gas(parse_gas!("300 Tgas") as u64)
-        .transact()
-        .await?
-        .json()?;
+    let result: U128 = defi_contract.view("get_token_ratio", vec![]).await?.json()?;
     println!("get ratio: {:?}", result);
 
     // swap
+    deposit(&alice, &ft_contract_a, &defi_contract, U128(10)).await?;
     let result = alice
         .call(defi_contract.id(), "swap_token")
         .args_json(json!({
@@ -210,17 +188,58 @@ async fn main() -> anyhow::Result<()> {
         .await?;
     println!("swap result is success: {:?}", result.is_success());
     assert!(result.is_success());
+    let swap_result: Value = result.json()?;
+    println!("swap result: {:?}", swap_result);
+    assert_eq!(swap_result["status"], "Success");
 
-    // get the TokenA again
-    let result: U128 = defi_contract
-        .as_account()
-        .call(defi_contract.id(), "get_swap_token")
-        .args_json(json!({"symbol": "TokenA"}))
-        .gas(parse_gas!("300 Tgas") as u64)
+    // withdraw the TokenB
+    let balance_before: U128 = ft_contract_b
+        .view("ft_balance_of", json!({"account_id": alice.id()}).to_string().into_bytes())
+        .await?
+        .json()?;
+    let withdraw_gas: U64 = defi_contract
+        .view("get_required_gas", json!({"method_name": "withdraw"}).to_string().into_bytes())
+        .await?
+        .json()?;
+    let result = alice
+        .call(defi_contract.id(), "withdraw")
+        .args_json(json!({
+            "token_id": ft_contract_b.id(),
+            "amount": swap_result["amount_out"],
+        }))
+        .deposit(1)
+        .gas(withdraw_gas.0 + parse_gas!("10 Tgas") as u64)
         .transact()
+        .await?;
+    assert!(result.is_success(), "withdraw failed");
+    let balance_after: U128 = ft_contract_b
+        .view("ft_balance_of", json!({"account_id": alice.id()}).to_string().into_bytes())
         .await?
         .json()?;
-    println!("TokenA in Swap: {:?}", result);
+    println!("TokenB of alice: {:?} -> {:?}", balance_before, balance_after);
+    assert!(balance_after.0 > balance_before.0);
+    Ok(())
+}
+
+/// send `amount` to the internal balance of `user` in the defi contract
+async fn deposit(
+    user: &Account,
+    contract: &Contract,
+    defi_contract: &Contract,
+    amount: U128,
+) -> anyhow::Result<()> {
+    let result = user
+        .call(contract.id(), "ft_transfer_call")
+        .args_json(json!({
+            "receiver_id": defi_contract.id(),
+            "amount": amount,
+            "msg": "",
+        }))
+        .deposit(1)
+        .gas(parse_gas!("300 Tgas") as u64)
+        .transact()
+        .await?;
+    assert!(result.is_success(), "deposit failed");
     Ok(())
 }
 